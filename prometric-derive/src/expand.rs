@@ -1,12 +1,17 @@
 use darling::{FromField, FromMeta};
-use proc_macro2::TokenStream;
+use proc_macro2::{Delimiter, Group, Span, TokenStream, TokenTree};
 use quote::{format_ident, quote};
 use syn::{
-    Field, GenericArgument, Ident, ItemStruct, LitFloat, LitStr, PathArguments, PathSegment,
-    Result, Type,
+    Field, GenericArgument, Ident, ItemStruct, LitFloat, LitInt, LitStr, PathArguments,
+    PathSegment, Result, Token, Type,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
 };
 
-use crate::utils::{snake_to_pascal, to_screaming_snake};
+use crate::utils::{
+    default_scope, is_reserved_label_name, is_valid_label_name, is_valid_metric_name,
+    is_valid_unit, parse_duration, snake_to_pascal, to_screaming_snake, with_unit_suffix,
+};
 
 /// The name of the metric attribute.
 const METRIC_ATTR_NAME: &str = "metric";
@@ -18,17 +23,36 @@ const DEFAULT_SEPARATOR: &str = "_";
 #[derive(FromMeta, Debug)]
 #[darling(derive_syn_parse)]
 pub(super) struct MetricsAttr {
-    /// The scope to use for the metrics. Used as a prefix for metric names.
+    /// The scope to use for the metrics. Used as a prefix for metric names. Defaults to the
+    /// snake_case name of the struct without the `Metrics` suffix.
     scope: Option<LitStr>,
     /// If true, generates a static LazyLock with SCREAMING_SNAKE_CASE name.
     #[darling(default, rename = "static")]
     _static: bool,
+    /// How the `_total` suffix of counter names is handled.
+    #[darling(default)]
+    counter_suffix: CounterSuffix,
+}
+
+/// How the `_total` suffix of counter names is handled, set with
+/// `#[metrics(counter_suffix = "...")]`.
+#[derive(FromMeta, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[darling(rename_all = "snake_case")]
+enum CounterSuffix {
+    /// Counter names are used as is.
+    #[default]
+    Off,
+    /// `_total` is appended to counter names that don't end with it.
+    Auto,
+    /// Counter names that don't end with `_total` are rejected.
+    Require,
 }
 
 enum MetricType {
     Counter(Ident, Type),
     Gauge(Ident, Type),
     Histogram(Ident),
+    Summary(Ident),
 }
 
 impl std::fmt::Display for MetricType {
@@ -37,6 +61,7 @@ impl std::fmt::Display for MetricType {
             Self::Counter(_, _) => write!(f, "Counter"),
             Self::Gauge(_, _) => write!(f, "Gauge"),
             Self::Histogram(_) => write!(f, "Histogram"),
+            Self::Summary(_) => write!(f, "Summary"),
         }
     }
 }
@@ -83,9 +108,13 @@ impl MetricType {
                 Ok(Self::Gauge(ident.clone(), generic))
             }
             "Histogram" => Ok(Self::Histogram(ident.clone())),
+            "Summary" => Ok(Self::Summary(ident.clone())),
             other => Err(syn::Error::new_spanned(
                 ident,
-                format!("Unsupported metric type '{other}'. Use Counter, Gauge, or Histogram"),
+                format!(
+                    "Unsupported metric type '{other}'. Use Counter, Gauge, Histogram, or Summary, or \
+                    mark the field with #[metric(skip)]"
+                ),
             )),
         }
     }
@@ -95,7 +124,317 @@ impl MetricType {
             Self::Counter(ident, ty) => quote! { #ident<#ty> },
             Self::Gauge(ident, ty) => quote! { #ident<#ty> },
             Self::Histogram(ident) => quote! { #ident },
+            Self::Summary(ident) => quote! { #ident },
+        }
+    }
+
+    /// The type of the handle returned by the accessor's `bind` method.
+    fn handle_type(&self) -> TokenStream {
+        match self {
+            Self::Counter(_, ty) => quote! { prometric::CounterHandle<#ty> },
+            Self::Gauge(_, ty) => quote! { prometric::GaugeHandle<#ty> },
+            Self::Histogram(_) => quote! { prometric::HistogramHandle },
+            Self::Summary(_) => quote! { prometric::SummaryHandle },
+        }
+    }
+}
+
+/// A label of a metric, optionally with the type of its values.
+struct Label {
+    name: String,
+    span: Span,
+    /// The type of the accessor argument. If not set, the accessor accepts any
+    /// `prometric::LabelValue`.
+    ty: Option<Type>,
+}
+
+impl Parse for Label {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let (name, span) = if input.peek(LitStr) {
+            let lit = input.parse::<LitStr>()?;
+            (lit.value(), lit.span())
+        } else {
+            let ident = input.parse::<Ident>()?;
+            (ident.to_string(), ident.span())
+        };
+
+        let ty = if input.peek(Token![:]) {
+            input.parse::<Token![:]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+
+        Ok(Self { name, span, ty })
+    }
+}
+
+impl Label {
+    /// Validate the label name against the Prometheus data model. Label names are also used as
+    /// accessor arguments, so they must be valid Rust identifiers as well.
+    fn validate(&self) -> Result<()> {
+        let name = &self.name;
+
+        if !is_valid_label_name(name) {
+            return Err(syn::Error::new(
+                self.span,
+                format!(
+                    "Invalid label name `{name}`. Label names must match [a-zA-Z_][a-zA-Z0-9_]*"
+                ),
+            ));
+        }
+
+        if is_reserved_label_name(name) {
+            return Err(syn::Error::new(
+                self.span,
+                format!(
+                    "Label name `{name}` is reserved. `le`, `quantile` and names starting with `__` \
+                    can't be used"
+                ),
+            ));
+        }
+
+        if syn::parse_str::<Ident>(name).is_err() {
+            return Err(syn::Error::new(
+                self.span,
+                format!(
+                    "Label name `{name}` is a Rust keyword and can't be used as an accessor argument"
+                ),
+            ));
+        }
+
+        if name == "inner" {
+            return Err(syn::Error::new(
+                self.span,
+                "Label name `inner` is reserved by the generated accessor",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Label {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.ty {
+            Some(ty) => write!(f, "{}: {}", self.name, quote! { #ty }),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// The `labels` attribute of a metric: either a list of label keys, or a type implementing
+/// `prometric::LabelSet`.
+enum LabelsAttr {
+    List(Vec<LitStr>),
+    Set(syn::Path),
+}
+
+impl FromMeta for LabelsAttr {
+    fn from_expr(expr: &syn::Expr) -> darling::Result<Self> {
+        match expr {
+            syn::Expr::Array(array) => array
+                .elems
+                .iter()
+                .map(|elem| match elem {
+                    syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(lit), .. }) => Ok(lit.clone()),
+                    other => Err(darling::Error::unexpected_expr_type(other).with_span(other)),
+                })
+                .collect::<darling::Result<_>>()
+                .map(Self::List),
+            syn::Expr::Path(path) => Ok(Self::Set(path.path.clone())),
+            other => Err(darling::Error::custom(
+                "Expected a list of labels or a type implementing `prometric::LabelSet`",
+            )
+            .with_span(other)),
+        }
+    }
+}
+
+/// Parse the labels of the metric attribute, which may be typed (e.g. `labels = [method:
+/// HttpMethod, "path"]`). Typed labels aren't valid meta syntax, so they are replaced with plain
+/// string labels before the attribute is handed to darling.
+fn take_typed_labels(field: &mut Field) -> Result<Option<Vec<Label>>> {
+    let mut labels = None;
+
+    for attr in field.attrs.iter_mut().filter(|attr| attr.path().is_ident(METRIC_ATTR_NAME)) {
+        let syn::Meta::List(list) = &mut attr.meta else {
+            continue;
+        };
+
+        let mut tokens: Vec<TokenTree> = list.tokens.clone().into_iter().collect();
+        for i in 0..tokens.len().saturating_sub(2) {
+            let (TokenTree::Ident(key), TokenTree::Punct(eq), TokenTree::Group(group)) =
+                (&tokens[i], &tokens[i + 1], &tokens[i + 2])
+            else {
+                continue;
+            };
+
+            if key != "labels" || eq.as_char() != '=' || group.delimiter() != Delimiter::Bracket {
+                continue;
+            }
+
+            let parsed = syn::parse::Parser::parse2(
+                Punctuated::<Label, Token![,]>::parse_terminated,
+                group.stream(),
+            )?;
+
+            let names = parsed.iter().map(|label| &label.name);
+            let mut plain = Group::new(Delimiter::Bracket, quote! { #(#names),* });
+            plain.set_span(group.span());
+            tokens[i + 2] = TokenTree::Group(plain);
+
+            labels = Some(parsed.into_iter().collect());
+        }
+
+        list.tokens = tokens.into_iter().collect();
+    }
+
+    Ok(labels)
+}
+
+/// The value of a numeric literal expression such as `1`, `0.5` or `-2.0`.
+fn literal_value(expr: &syn::Expr) -> Option<f64> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Float(lit), .. }) => lit.base10_parse().ok(),
+        syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(lit), .. }) => lit.base10_parse().ok(),
+        syn::Expr::Unary(syn::ExprUnary { op: syn::UnOp::Neg(_), expr, .. }) => {
+            literal_value(expr).map(|value| -value)
+        }
+        syn::Expr::Paren(syn::ExprParen { expr, .. }) => literal_value(expr),
+        _ => None,
+    }
+}
+
+/// Whether the expression is a non-finite literal, e.g. `1e400`, or a path to a non-finite constant,
+/// e.g. `f64::INFINITY`.
+fn is_non_finite(expr: &syn::Expr) -> bool {
+    match expr {
+        syn::Expr::Path(path) => {
+            let last = path.path.segments.last().map(|segment| segment.ident.to_string());
+            matches!(last.as_deref(), Some("INFINITY" | "NEG_INFINITY" | "NAN"))
+        }
+        syn::Expr::Unary(syn::ExprUnary { op: syn::UnOp::Neg(_), expr, .. }) => is_non_finite(expr),
+        _ => literal_value(expr).is_some_and(|value| !value.is_finite()),
+    }
+}
+
+/// Resolve and validate the `buckets` attribute of a histogram.
+///
+/// The bucket helpers of `prometric::buckets` can be called without the path, with the number of
+/// buckets as last argument, e.g. `exponential(0.001, 2.0, 15)`. Literal arrays and the arguments of
+/// the helpers are validated at compile time.
+fn resolve_buckets(expr: syn::Expr) -> Result<syn::Expr> {
+    match &expr {
+        syn::Expr::Array(array) => {
+            let mut previous: Option<f64> = None;
+            for elem in &array.elems {
+                if is_non_finite(elem) {
+                    return Err(syn::Error::new_spanned(
+                        elem,
+                        "Buckets must be finite. The `+Inf` bucket is always added",
+                    ));
+                }
+
+                let Some(value) = literal_value(elem) else {
+                    continue;
+                };
+                if previous.is_some_and(|previous| value <= previous) {
+                    return Err(syn::Error::new_spanned(
+                        elem,
+                        "Buckets must be strictly increasing",
+                    ));
+                }
+                previous = Some(value);
+            }
+
+            Ok(expr)
+        }
+        syn::Expr::Call(call) => {
+            let syn::Expr::Path(func) = call.func.as_ref() else {
+                return Ok(expr);
+            };
+            let Some(helper) = func.path.get_ident() else {
+                return Ok(expr);
+            };
+
+            let args = call.args.iter().collect::<Vec<_>>();
+            match (helper.to_string().as_str(), args.as_slice()) {
+                ("duration_seconds_default", []) => {
+                    Ok(syn::parse_quote! { prometric::buckets::duration_seconds_default() })
+                }
+                (name @ ("exponential" | "linear" | "exponential_range"), [a, b, count]) => {
+                    let count = match count {
+                        syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(lit), .. }) => {
+                            lit.base10_parse::<usize>()?
+                        }
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                count,
+                                "The number of buckets must be an integer literal",
+                            ));
+                        }
+                    };
+
+                    let error = match (name, literal_value(a), literal_value(b)) {
+                        (_, _, _) if count == 0 => Some("The number of buckets must be positive"),
+                        (_, _, _) if is_non_finite(a) || is_non_finite(b) => {
+                            Some("The arguments of bucket helpers must be finite")
+                        }
+                        ("exponential", Some(start), _) if start <= 0.0 => {
+                            Some("The start of exponential buckets must be positive")
+                        }
+                        ("exponential", _, Some(factor)) if factor <= 1.0 => {
+                            Some("The factor of exponential buckets must be greater than 1")
+                        }
+                        ("linear", _, Some(width)) if width <= 0.0 => {
+                            Some("The width of linear buckets must be positive")
+                        }
+                        ("exponential_range", _, _) if count < 2 => {
+                            Some("The number of buckets in a range must be at least 2")
+                        }
+                        ("exponential_range", Some(min), _) if min <= 0.0 => {
+                            Some("The minimum of exponential buckets must be positive")
+                        }
+                        ("exponential_range", Some(min), Some(max)) if max <= min => Some(
+                            "The maximum of exponential buckets must be greater than the minimum",
+                        ),
+                        _ => None,
+                    };
+                    if let Some(error) = error {
+                        return Err(syn::Error::new_spanned(call, error));
+                    }
+
+                    let count = syn::LitInt::new(&count.to_string(), Span::call_site());
+                    Ok(syn::parse_quote! { prometric::buckets::#helper::<#count>(#a, #b) })
+                }
+                _ => Ok(expr),
+            }
+        }
+        _ => Ok(expr),
+    }
+}
+
+/// The buckets of a histogram as a constant expression, if they're known at compile time.
+fn const_buckets(expr: &syn::Expr) -> Option<TokenStream> {
+    match expr {
+        syn::Expr::Array(array) => {
+            let values = array.elems.iter().map(literal_value).collect::<Option<Vec<_>>>()?;
+            Some(quote! { &[#(#values),*] })
+        }
+        syn::Expr::Call(call) => {
+            let syn::Expr::Path(func) = call.func.as_ref() else {
+                return None;
+            };
+
+            // Only the resolved bucket helpers are const functions.
+            let segments =
+                func.path.segments.iter().map(|s| s.ident.to_string()).collect::<Vec<_>>();
+            let is_helper = segments.len() == 3 && segments[..2] == ["prometric", "buckets"];
+            (is_helper && call.args.iter().all(|arg| literal_value(arg).is_some()))
+                .then(|| quote! { &#expr })
         }
+        _ => None,
     }
 }
 
@@ -105,21 +444,42 @@ struct MetricBuilder {
     identifier: Ident,
     /// The type of the metric.
     ty: MetricType,
-    /// The label keys to define for the metric.
-    labels: Option<Vec<String>>,
+    /// The labels to define for the metric.
+    labels: Option<Vec<Label>>,
+    /// The label set type to use instead of individual labels.
+    label_set: Option<syn::Path>,
     /// The buckets to use for the histogram.
     buckets: Option<syn::Expr>,
-    /// The full name of the metric.
-    /// = scope + separator + identifier || rename.
-    full_name: String,
+    /// The quantiles to use for the summary.
+    quantiles: Option<syn::Expr>,
+    /// The maximum age of observations for the summary.
+    max_age: Option<(String, std::time::Duration)>,
+    /// The fraction of observations to record for histograms and summaries.
+    sample: Option<LitFloat>,
+    /// The unit of the metric, e.g. `seconds`, which is also appended to the name.
+    unit: Option<String>,
+    /// The maximum number of label combinations of the metric.
+    max_cardinality: Option<usize>,
+    /// The label value that new label combinations are folded into once the limit is reached.
+    overflow: Option<String>,
+    /// The time after which series that haven't been updated are removed.
+    ttl: Option<(String, std::time::Duration)>,
+    /// The name of the metric (identifier || rename), including the unit suffix. The full name is built at runtime from the
+    /// scope of the builder, so that nested metrics structs can be re-scoped.
+    /// = scope + separator + name.
+    name: String,
     /// The doc string of the metric.
     help: String,
 }
 
 impl MetricBuilder {
-    fn try_from(field: &Field, scope: &str) -> Result<Self> {
-        let metric_field = MetricField::from_field(field)?;
-
+    fn try_from(
+        field: &Field,
+        metric_field: MetricField,
+        typed_labels: Option<Vec<Label>>,
+        scope: &str,
+        counter_suffix: CounterSuffix,
+    ) -> Result<Self> {
         let help = metric_field
             .help
             .or_else(|| {
@@ -148,38 +508,265 @@ impl MetricBuilder {
             })
             .unwrap_or_default();
 
-        let metric_name = metric_field
+        let Type::Path(type_path) = &metric_field.ty else {
+            return Err(syn::Error::new_spanned(field, "Expected a path type"));
+        };
+
+        let last_segment = type_path.path.segments.last().unwrap();
+
+        let ty = MetricType::from_segment(last_segment)?;
+
+        let mut metric_name = metric_field
             .rename
             .as_ref()
             .unwrap_or(&field.ident.as_ref().unwrap().to_string())
             .to_owned();
 
+        let unit = metric_field.unit.as_ref().map(LitStr::value);
+        if let Some(unit) = &unit {
+            if !is_valid_unit(unit) {
+                return Err(syn::Error::new_spanned(
+                    metric_field.unit.as_ref(),
+                    format!(
+                        "Invalid unit `{unit}`. Units must be lowercase and match [a-z][a-z0-9_]*, \
+                        e.g. \"seconds\" or \"bytes\""
+                    ),
+                ));
+            }
+
+            metric_name =
+                with_unit_suffix(&metric_name, unit, matches!(ty, MetricType::Counter(_, _)));
+        }
+
+        if matches!(ty, MetricType::Counter(_, _)) && !metric_name.ends_with("_total") {
+            match counter_suffix {
+                CounterSuffix::Off => {}
+                CounterSuffix::Auto => metric_name.push_str("_total"),
+                CounterSuffix::Require => {
+                    return Err(syn::Error::new_spanned(
+                        field,
+                        format!(
+                            "Counter name `{metric_name}` must end with `_total` \
+                            (`counter_suffix = \"require\"`). Use e.g. \
+                            `#[metric(rename = \"{metric_name}_total\")]`"
+                        ),
+                    ));
+                }
+            }
+        }
+
         let full_name = format!("{scope}{DEFAULT_SEPARATOR}{metric_name}");
+        if !is_valid_metric_name(&full_name) {
+            return Err(syn::Error::new_spanned(
+                field,
+                format!(
+                    "Invalid metric name `{full_name}`. Metric names must match \
+                    [a-zA-Z_:][a-zA-Z0-9_:]*"
+                ),
+            ));
+        }
 
-        let Type::Path(type_path) = &metric_field.ty else {
-            return Err(syn::Error::new_spanned(field, "Expected a path type"));
+        let is_histogram = matches!(ty, MetricType::Histogram(_));
+        if let Some(buckets) = metric_field.buckets.as_ref().filter(|_| !is_histogram) {
+            return Err(syn::Error::new_spanned(
+                buckets,
+                "`buckets` is only supported for Histogram metrics",
+            ));
+        }
+
+        if !matches!(ty, MetricType::Summary(_)) {
+            if let Some(quantiles) = &metric_field.quantiles {
+                return Err(syn::Error::new_spanned(
+                    quantiles,
+                    "`quantiles` is only supported for Summary metrics",
+                ));
+            }
+
+            if let Some(max_age) = &metric_field.max_age {
+                return Err(syn::Error::new_spanned(
+                    max_age,
+                    "`max_age` is only supported for Summary metrics",
+                ));
+            }
+        }
+
+        if let Some(sample) = &metric_field.sample {
+            if !matches!(ty, MetricType::Histogram(_) | MetricType::Summary(_)) {
+                return Err(syn::Error::new_spanned(
+                    sample,
+                    "`sample` is only supported for Histogram and Summary metrics",
+                ));
+            }
+
+            let rate: f64 = sample.base10_parse()?;
+            if !(rate > 0.0 && rate <= 1.0) {
+                return Err(syn::Error::new_spanned(
+                    sample,
+                    "The sample rate must be in the interval (0, 1]",
+                ));
+            }
+        }
+
+        if let Some(syn::Expr::Array(quantiles)) = &metric_field.quantiles {
+            for quantile in &quantiles.elems {
+                if let syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Float(lit), .. }) = quantile {
+                    let value: f64 = lit.base10_parse()?;
+                    if !(value > 0.0 && value < 1.0) {
+                        return Err(syn::Error::new_spanned(
+                            lit,
+                            "Quantiles must be in the open interval (0, 1)",
+                        ));
+                    }
+                }
+            }
+        }
+
+        let has_labels = metric_field.labels.is_some();
+        let max_cardinality = metric_field
+            .max_cardinality
+            .as_ref()
+            .map(|max| {
+                if !has_labels {
+                    return Err(syn::Error::new_spanned(
+                        max,
+                        "`max_cardinality` requires the metric to have labels",
+                    ));
+                }
+
+                match max.base10_parse::<usize>()? {
+                    0 => {
+                        Err(syn::Error::new_spanned(max, "The cardinality limit must be positive"))
+                    }
+                    max => Ok(max),
+                }
+            })
+            .transpose()?;
+
+        if let Some(overflow) = metric_field.overflow.as_ref().filter(|_| max_cardinality.is_none())
+        {
+            return Err(syn::Error::new_spanned(
+                overflow,
+                "`overflow` is only supported together with `max_cardinality`",
+            ));
+        }
+
+        let ttl = metric_field
+            .ttl
+            .map(|ttl| {
+                if !has_labels {
+                    return Err(syn::Error::new_spanned(
+                        ttl,
+                        "`ttl` requires the metric to have labels",
+                    ));
+                }
+
+                match parse_duration(&ttl.value()) {
+                    Some(duration) if !duration.is_zero() => Ok((ttl.value(), duration)),
+                    _ => Err(syn::Error::new_spanned(
+                        ttl,
+                        "Invalid duration. Expected a non-zero duration such as \"30s\", \"5m\" or \"1h\"",
+                    )),
+                }
+            })
+            .transpose()?;
+
+        let max_age = metric_field
+            .max_age
+            .map(|max_age| match parse_duration(&max_age.value()) {
+                Some(duration) if !duration.is_zero() => Ok((max_age.value(), duration)),
+                _ => Err(syn::Error::new_spanned(
+                    max_age,
+                    "Invalid duration. Expected a non-zero duration such as \"30s\", \"10m\" or \"1h\"",
+                )),
+            })
+            .transpose()?;
+
+        let (labels, label_set) = match metric_field.labels {
+            Some(LabelsAttr::Set(path)) => (None, Some(path)),
+            Some(LabelsAttr::List(labels)) => (
+                typed_labels.or_else(|| {
+                    Some(
+                        labels
+                            .iter()
+                            .map(|label| Label {
+                                name: label.value(),
+                                span: label.span(),
+                                ty: None,
+                            })
+                            .collect(),
+                    )
+                }),
+                None,
+            ),
+            None => (None, None),
         };
 
-        let last_segment = type_path.path.segments.last().unwrap();
+        let mut seen = std::collections::HashSet::new();
+        for label in labels.iter().flatten() {
+            label.validate()?;
 
-        let ty = MetricType::from_segment(last_segment)?;
+            if !seen.insert(&label.name) {
+                return Err(syn::Error::new(
+                    label.span,
+                    format!("Duplicate label `{}`", label.name),
+                ));
+            }
+        }
 
         Ok(Self {
             identifier: metric_field
                 .ident
                 .ok_or(syn::Error::new_spanned(field, "Expected an identifier"))?,
             ty,
-            labels: metric_field
-                .labels
-                .map(|labels| labels.iter().map(|label| label.value()).collect()),
-            buckets: metric_field.buckets,
-            full_name,
+            labels,
+            label_set,
+            buckets: metric_field.buckets.map(resolve_buckets).transpose()?,
+            quantiles: metric_field.quantiles,
+            max_age,
+            sample: metric_field.sample,
+            unit,
+            max_cardinality,
+            overflow: metric_field.overflow.as_ref().map(LitStr::value),
+            ttl,
+            name: metric_name,
             help,
         })
     }
 
-    fn labels(&self) -> Vec<String> {
-        self.labels.clone().unwrap_or_default()
+    /// The names of all series exposed by the metric with the given scope, including the
+    /// histogram and summary suffixes and the companion counter of sampled metrics.
+    fn series_names(&self, scope: &str) -> Vec<String> {
+        let name = format!("{scope}{DEFAULT_SEPARATOR}{}", self.name);
+
+        let mut series = match self.ty {
+            MetricType::Histogram(_) => {
+                vec![format!("{name}_bucket"), format!("{name}_sum"), format!("{name}_count")]
+            }
+            MetricType::Summary(_) => {
+                vec![name.clone(), format!("{name}_sum"), format!("{name}_count")]
+            }
+            _ => vec![name.clone()],
+        };
+
+        if self.sample.is_some() {
+            series.push(format!("{name}_observations_total"));
+        }
+
+        series
+    }
+
+    fn labels(&self) -> &[Label] {
+        self.labels.as_deref().unwrap_or_default()
+    }
+
+    /// The expression for the label names passed to the metric constructor.
+    fn label_names(&self) -> TokenStream {
+        if let Some(label_set) = &self.label_set {
+            return quote! { <#label_set as prometric::LabelSet>::LABEL_NAMES };
+        }
+
+        let names = self.labels().iter().map(|label| &label.name);
+        quote! { &[#(#names),*] }
     }
 
     /// Build the initializer for the metric field.
@@ -187,28 +774,125 @@ impl MetricBuilder {
         let ident = &self.identifier;
         let help = &self.help;
         let ty = self.ty.full_type();
-        let name = &self.full_name;
-        let labels = self.labels();
+        let name = &self.name;
+        let name = quote! { &format!("{}{}{}", self.scope, #DEFAULT_SEPARATOR, #name) };
+        let labels = self.label_names();
         let buckets = &self.buckets;
+        let sample = match &self.sample {
+            Some(rate) => quote! { Some(#rate) },
+            None => quote! { None },
+        };
+        let unit = match &self.unit {
+            Some(unit) => quote! { Some(#unit) },
+            None => quote! { None },
+        };
+        let max_cardinality = self.max_cardinality.iter();
+        let overflow = self.overflow.iter();
+        let ttl = self.ttl.iter().map(|(_, ttl)| {
+            let millis = ttl.as_millis() as u64;
+            quote! { std::time::Duration::from_millis(#millis) }
+        });
+        let series = quote! {
+            prometric::SeriesOptions::new()
+                #(.max_cardinality(#max_cardinality))*
+                #(.overflow_value(#overflow))*
+                #(.ttl(#ttl))*
+        };
 
-        if let MetricType::Histogram(_) = &self.ty {
-            let buckets = if let Some(buckets_expr) = buckets {
-                quote! { Some(#buckets_expr) }
-            } else {
-                quote! { None }
-            };
+        match &self.ty {
+            MetricType::Histogram(_) => {
+                // Buckets set on the builder take precedence over the attribute.
+                let field = ident.to_string();
+                let buckets = if let Some(buckets_expr) = buckets {
+                    quote! {
+                        self.buckets.get(#field).cloned().or_else(|| Some((#buckets_expr).into()))
+                    }
+                } else {
+                    quote! { self.buckets.get(#field).cloned() }
+                };
 
-            quote! {
-                #ident: <#ty>::new(self.registry, #name, #help, &[#(#labels),*], self.labels.clone(), #buckets)
+                quote! {
+                    #ident: <#ty>::try_new(self.registry, #name, #help, #labels, self.labels.clone(), #buckets, #sample, #unit, #series, self.on_conflict)?
+                }
             }
-        } else {
-            quote! {
-                #ident: <#ty>::new(self.registry, #name, #help, &[#(#labels),*], self.labels.clone())
+            MetricType::Summary(_) => {
+                let quantiles = if let Some(quantiles_expr) = &self.quantiles {
+                    quote! { Some(#quantiles_expr) }
+                } else {
+                    quote! { None::<Vec<f64>> }
+                };
+
+                let max_age = if let Some((_, max_age)) = &self.max_age {
+                    let millis = max_age.as_millis() as u64;
+                    quote! { Some(std::time::Duration::from_millis(#millis)) }
+                } else {
+                    quote! { None }
+                };
+
+                quote! {
+                    #ident: <#ty>::try_new(self.registry, #name, #help, #labels, self.labels.clone(), #quantiles, #max_age, #sample, #unit, #series, self.on_conflict)?
+                }
             }
+            _ => quote! {
+                #ident: <#ty>::try_new(self.registry, #name, #help, #labels, self.labels.clone(), #unit, #series, self.on_conflict)?
+            },
         }
     }
 
-    fn accessor_doc(&self, labels: &[String]) -> String {
+    /// Build the descriptor of the metric with the default scope.
+    fn build_descriptor(&self, scope: &str) -> TokenStream {
+        let field = self.identifier.to_string();
+        let name = format!("{scope}{DEFAULT_SEPARATOR}{}", self.name);
+        let kind = match self.ty {
+            MetricType::Counter(_, _) => quote! { COUNTER },
+            MetricType::Gauge(_, _) => quote! { GAUGE },
+            MetricType::Histogram(_) => quote! { HISTOGRAM },
+            MetricType::Summary(_) => quote! { SUMMARY },
+        };
+        let help = &self.help;
+        let labels = self.label_names();
+        let unit = match &self.unit {
+            Some(unit) => quote! { Some(#unit) },
+            None => quote! { None },
+        };
+        let buckets = match (&self.ty, &self.buckets) {
+            (MetricType::Histogram(_), None) => {
+                quote! { Some(prometheus::DEFAULT_BUCKETS as &[f64]) }
+            }
+            (MetricType::Histogram(_), Some(buckets)) => match const_buckets(buckets) {
+                Some(buckets) => quote! { Some(#buckets) },
+                None => quote! { None },
+            },
+            _ => quote! { None },
+        };
+
+        quote! {
+            prometric::MetricDescriptor {
+                field: #field,
+                name: std::borrow::Cow::Borrowed(#name),
+                kind: prometheus::proto::MetricType::#kind,
+                help: #help,
+                labels: #labels,
+                unit: #unit,
+                buckets: #buckets,
+            }
+        }
+    }
+
+    /// Build the statement that adds the descriptor with the given index to the descriptors with
+    /// the scope in `scope`.
+    fn build_scoped_descriptor(&self, index: usize) -> TokenStream {
+        let name = &self.name;
+
+        quote! {
+            descriptors.push(prometric::MetricDescriptor {
+                name: std::borrow::Cow::Owned(format!("{}{}{}", scope, #DEFAULT_SEPARATOR, #name)),
+                ..<Self as prometric::MetricsGroup>::DESCRIPTORS[#index].clone()
+            });
+        }
+    }
+
+    fn accessor_doc(&self, labels: &[Label]) -> String {
         let help = &self.help;
         let mut doc_builder = format!(
             "{help}\n\
@@ -216,7 +900,10 @@ impl MetricBuilder {
             self.ty,
         );
 
-        if !labels.is_empty() {
+        if let Some(label_set) = &self.label_set {
+            doc_builder.push_str(&format!("\n* Labels: [`{}`]\n", quote! { #label_set }));
+        } else if !labels.is_empty() {
+            let labels = labels.iter().map(ToString::to_string).collect::<Vec<_>>();
             doc_builder.push_str(&format!("\n* Labels: {}\n", labels.join(", ")));
         }
 
@@ -228,6 +915,37 @@ impl MetricBuilder {
             }
         }
 
+        if let MetricType::Summary(_) = &self.ty {
+            if let Some(quantiles_expr) = &self.quantiles {
+                doc_builder.push_str(&format!("\n* Quantiles: {}", quote! { #quantiles_expr }));
+            } else {
+                doc_builder.push_str("\n* Quantiles: [prometric::DEFAULT_QUANTILES]");
+            }
+
+            if let Some((max_age, _)) = &self.max_age {
+                doc_builder.push_str(&format!("\n* Max age: {max_age}"));
+            } else {
+                doc_builder.push_str("\n* Max age: [prometric::DEFAULT_MAX_AGE]");
+            }
+        }
+
+        if let Some(rate) = &self.sample {
+            doc_builder.push_str(&format!("\n* Sample rate: {rate}"));
+        }
+
+        if let Some(unit) = &self.unit {
+            doc_builder.push_str(&format!("\n* Unit: {unit}"));
+        }
+
+        if let Some(max) = &self.max_cardinality {
+            let overflow = self.overflow.as_deref().unwrap_or("__overflow__");
+            doc_builder.push_str(&format!("\n* Max cardinality: {max} (overflow: `{overflow}`)"));
+        }
+
+        if let Some((ttl, _)) = &self.ttl {
+            doc_builder.push_str(&format!("\n* TTL: {ttl}"));
+        }
+
         doc_builder
     }
 
@@ -239,15 +957,28 @@ impl MetricBuilder {
 
         let accessor_name = format_ident!("{}Accessor", snake_to_pascal(&ident.to_string()));
 
-        let label_definitions = labels.iter().map(|label| {
-            let label_ident = format_ident!("{label}");
-            quote! { #label_ident: String }
-        });
+        let (label_definitions, label_arguments, label_assignments): (Vec<_>, Vec<_>, Vec<_>) =
+            if let Some(label_set) = &self.label_set {
+                let definition = quote! { labels: &'a #label_set };
+                (vec![definition.clone()], vec![definition], vec![quote! { labels }])
+            } else {
+                labels
+                    .iter()
+                    .map(|label| {
+                        let label_ident = format_ident!("{}", label.name);
+                        let argument = match &label.ty {
+                            Some(ty) => quote! { #label_ident: #ty },
+                            None => quote! { #label_ident: impl prometric::LabelValue<'a> },
+                        };
 
-        let label_arguments = labels.iter().map(|label| {
-            let label_ident = format_ident!("{label}");
-            quote! { #label_ident: impl Into<String> }
-        });
+                        (
+                            quote! { #label_ident: std::borrow::Cow<'a, str> },
+                            argument,
+                            quote! { #label_ident: prometric::LabelValue::into_label_value(#label_ident) },
+                        )
+                    })
+                    .collect()
+            };
 
         let def_doc = format!("Accessor for the `{ident}` metric.");
         let definition = quote! {
@@ -258,17 +989,12 @@ impl MetricBuilder {
             }
         };
 
-        let accessor_doc = self.accessor_doc(&labels);
-
-        let label_assignments = labels.iter().map(|label| {
-            let label_ident = format_ident!("{label}");
-            quote! { #label_ident: #label_ident.into() }
-        });
+        let accessor_doc = self.accessor_doc(labels);
 
         let accessor = quote! {
             #[doc = #accessor_doc]
             #[must_use = "This doesn't do anything unless the metric value is changed"]
-            #vis fn #ident(&self, #(#label_arguments),*) -> #accessor_name {
+            #vis fn #ident<'a>(&'a self, #(#label_arguments),*) -> #accessor_name<'a> {
                 #accessor_name {
                     inner: &self.#ident,
                     #(#label_assignments),*
@@ -279,97 +1005,464 @@ impl MetricBuilder {
         (definition, accessor)
     }
 
+    /// Build the method that removes all series of the metric.
+    fn build_clear(&self, vis: &syn::Visibility) -> TokenStream {
+        let ident = &self.identifier;
+        let method = format_ident!("clear_{}", ident);
+        let doc = format!(
+            "Remove all series of the `{ident}` metric with the static label values of this \
+            instance."
+        );
+
+        quote! {
+            #[doc = #doc]
+            #vis fn #method(&self) {
+                self.#ident.clear();
+            }
+        }
+    }
+
     fn build_accessor_impl(&self, vis: &syn::Visibility) -> TokenStream {
         let ident = &self.identifier;
         let labels = self.labels();
         let ty = &self.ty;
 
         let accessor_name = format_ident!("{}Accessor", snake_to_pascal(&ident.to_string()));
-        let label_idents = labels.iter().map(|label| format_ident!("{label}"));
+        let label_idents = labels.iter().map(|label| format_ident!("{}", label.name));
 
-        let labels_array = if labels.is_empty() {
-            quote! { let labels = &[]; }
+        let with_labels = if self.label_set.is_some() {
+            quote! { prometric::LabelSet::with_label_values(self.labels, f) }
+        } else if labels.is_empty() {
+            quote! { f(&[]) }
         } else {
-            quote! { let labels = &[#(self.#label_idents.as_str()),*]; }
+            quote! { f(&[#(self.#label_idents.as_ref()),*]) }
         };
 
         let terminal_methods = match ty {
             MetricType::Counter(_, counter_ty) => quote! {
                 #vis fn inc(&self) {
-                    #labels_array
-                    self.inner.inc(labels);
+                    self.with_labels(|labels| self.inner.inc(labels));
                 }
 
                 #vis fn inc_by<V>(&self, value: V)
                 where
                     V: prometric::IntoAtomic<#counter_ty>,
                 {
-                    #labels_array
-                    self.inner.inc_by(labels, value.into_atomic());
+                    self.with_labels(|labels| self.inner.inc_by(labels, value.into_atomic()));
                 }
 
                 #vis fn reset(&self) {
-                    #labels_array
-                    self.inner.reset(labels);
+                    self.with_labels(|labels| self.inner.reset(labels));
+                }
+
+                /// Returns the current value of the counter, or zero if the series doesn't exist. This
+                /// scans all series of the metric, so bind a handle for frequent reads.
+                #vis fn get(&self) -> #counter_ty {
+                    self.with_labels(|labels| self.inner.get(labels))
                 }
             },
             MetricType::Gauge(_, gauge_ty) => quote! {
                 #vis fn inc(&self) {
-                    #labels_array
-                    self.inner.inc(labels);
+                    self.with_labels(|labels| self.inner.inc(labels));
                 }
 
                 #vis fn dec(&self) {
-                    #labels_array
-                    self.inner.dec(labels);
+                    self.with_labels(|labels| self.inner.dec(labels));
                 }
 
                 #vis fn add<V>(&self, value: V)
                 where
                     V: prometric::IntoAtomic<#gauge_ty>,
                 {
-                    #labels_array
-                    self.inner.add(labels, value.into_atomic());
+                    self.with_labels(|labels| self.inner.add(labels, value.into_atomic()));
                 }
 
                 #vis fn sub<V>(&self, value: V)
                 where
                     V: prometric::IntoAtomic<#gauge_ty>,
                 {
-                    #labels_array
-                    self.inner.sub(labels, value.into_atomic());
+                    self.with_labels(|labels| self.inner.sub(labels, value.into_atomic()));
                 }
 
                 #vis fn set<V>(&self, value: V)
                 where
                     V: prometric::IntoAtomic<#gauge_ty>,
                 {
-                    #labels_array
-                    self.inner.set(labels, value.into_atomic());
+                    self.with_labels(|labels| self.inner.set(labels, value.into_atomic()));
                 }
-            },
-            MetricType::Histogram(_) => quote! {
-                #vis fn observe<V>(&self, value: V)
+
+                /// Returns the current value of the gauge, or zero if the series doesn't exist. This
+                /// scans all series of the metric, so bind a handle for frequent reads.
+                #vis fn get(&self) -> #gauge_ty {
+                    self.with_labels(|labels| self.inner.get(labels))
+                }
+
+                /// Increment the gauge, and decrement it again when the returned guard is dropped.
+                #vis fn track_inflight(&self) -> prometric::InflightGuard<#gauge_ty> {
+                    prometric::InflightGuard::new(self.bind())
+                }
+
+                /// Wrap the future so that the gauge is incremented until the future completes or
+                /// is dropped.
+                #vis fn track_future<F>(&self, future: F) -> impl std::future::Future<Output = F::Output> + use<F>
                 where
-                    V: prometric::IntoAtomic<f64>,
+                    F: std::future::Future,
                 {
-                    #labels_array
-                    self.inner.observe(labels, value.into_atomic());
+                    let guard = self.track_inflight();
+
+                    async move {
+                        let _guard = guard;
+                        future.await
+                    }
                 }
             },
+            MetricType::Histogram(_) | MetricType::Summary(_) => {
+                let handle_ty = ty.handle_type();
+
+                // Metrics in seconds also accept a `Duration`.
+                let observe = if self.unit.as_deref() == Some("seconds") {
+                    quote! {
+                        #vis fn observe<V>(&self, value: V)
+                        where
+                            V: prometric::IntoSeconds,
+                        {
+                            self.with_labels(|labels| self.inner.observe(labels, value.into_seconds()));
+                        }
+                    }
+                } else {
+                    quote! {
+                        #vis fn observe<V>(&self, value: V)
+                        where
+                            V: prometric::IntoAtomic<f64>,
+                        {
+                            self.with_labels(|labels| self.inner.observe(labels, value.into_atomic()));
+                        }
+                    }
+                };
+
+                let snapshot = matches!(ty, MetricType::Histogram(_)).then(|| {
+                    quote! {
+                        /// Returns a snapshot of the observations, with their count, sum and
+                        /// cumulative bucket counts. This scans all series of the metric, so bind a
+                        /// handle for frequent reads.
+                        #vis fn snapshot(&self) -> prometric::HistogramSnapshot {
+                            self.with_labels(|labels| self.inner.snapshot(labels))
+                        }
+                    }
+                });
+
+                quote! {
+                    #observe
+
+                    #snapshot
+
+                    /// Start a timer that records the elapsed time in seconds when dropped.
+                    #vis fn start_timer(&self) -> prometric::Timer<#handle_ty> {
+                        prometric::Timer::new(self.bind())
+                    }
+
+                    /// Run the closure and record its execution time in seconds.
+                    #vis fn time<F, T>(&self, f: F) -> T
+                    where
+                        F: FnOnce() -> T,
+                    {
+                        let _timer = self.start_timer();
+                        f()
+                    }
+
+                    /// Wrap the future so that the time from its first poll until its completion is
+                    /// recorded in seconds. Nothing is recorded if the future is dropped before it
+                    /// completes.
+                    #vis fn time_future<F>(&self, future: F) -> impl std::future::Future<Output = F::Output> + use<F>
+                    where
+                        F: std::future::Future,
+                    {
+                        let handle = self.bind();
+
+                        async move {
+                            let start = std::time::Instant::now();
+                            let output = future.await;
+                            handle.observe(start.elapsed().as_secs_f64());
+                            output
+                        }
+                    }
+                }
+            }
         };
 
+        let handle_ty = ty.handle_type();
+
         quote! {
             impl<'a> #accessor_name<'a> {
+                /// Call the closure with the label values of the accessor.
+                #[inline]
+                fn with_labels<R>(&self, f: impl FnOnce(&[&str]) -> R) -> R {
+                    #with_labels
+                }
+
                 #terminal_methods
+
+                /// Remove the series of the metric with the label values of the accessor.
+                #vis fn remove(&self) {
+                    self.with_labels(|labels| self.inner.remove(labels));
+                }
+
+                /// Resolve the labels once and return an owned handle to the metric. Updating the
+                /// handle skips the label lookup, which makes it suitable for hot loops.
+                #vis fn bind(&self) -> #handle_ty {
+                    self.with_labels(|labels| self.inner.bind(labels))
+                }
             }
         }
     }
 }
 
+/// A builder for fields that are nested metrics structs, marked with `#[metric(flatten)]` or
+/// `#[metric(scope = "...")]`.
+struct NestedBuilder {
+    identifier: Ident,
+    ty: Type,
+    /// The sub-scope to append to the scope of the parent. If not set, the nested metrics share
+    /// the scope of the parent.
+    scope: Option<String>,
+}
+
+impl NestedBuilder {
+    fn try_from(field: &Field, metric_field: MetricField) -> Result<Self> {
+        if metric_field.flatten && metric_field.scope.is_some() {
+            return Err(syn::Error::new_spanned(
+                field,
+                "`flatten` and `scope` are mutually exclusive",
+            ));
+        }
+
+        let MetricField {
+            ident,
+            ty,
+            rename,
+            labels,
+            help,
+            buckets,
+            quantiles,
+            max_age,
+            sample,
+            unit,
+            max_cardinality,
+            overflow,
+            ttl,
+            flatten: _,
+            scope,
+            skip,
+            default,
+        } = metric_field;
+
+        if skip
+            || default.is_some()
+            || rename.is_some()
+            || labels.is_some()
+            || help.is_some()
+            || buckets.is_some()
+            || quantiles.is_some()
+            || max_age.is_some()
+            || sample.is_some()
+            || unit.is_some()
+            || max_cardinality.is_some()
+            || overflow.is_some()
+            || ttl.is_some()
+        {
+            return Err(syn::Error::new_spanned(
+                field,
+                "Nested metrics only support the `flatten` or `scope` attributes",
+            ));
+        }
+
+        if let Some(scope) = scope.as_ref().filter(|scope| !is_valid_metric_name(&scope.value())) {
+            return Err(syn::Error::new_spanned(
+                scope,
+                "Invalid scope. Scopes must match [a-zA-Z_:][a-zA-Z0-9_:]*",
+            ));
+        }
+
+        Ok(Self {
+            identifier: ident.ok_or(syn::Error::new_spanned(field, "Expected an identifier"))?,
+            ty,
+            scope: scope.map(|scope| scope.value()),
+        })
+    }
+
+    /// Build the initializer for the nested metrics, which forwards the registry, static labels
+    /// and scope to the nested builder.
+    fn build_initializer(&self) -> TokenStream {
+        let ident = &self.identifier;
+        let ty = &self.ty;
+
+        let scope = match &self.scope {
+            Some(scope) => quote! { format!("{}{}{}", self.scope, #DEFAULT_SEPARATOR, #scope) },
+            None => quote! { self.scope.clone() },
+        };
+
+        quote! {
+            #ident: <#ty>::builder()
+                .with_registry(self.registry)
+                .with_labels(self.labels.clone())
+                .with_scope(#scope)
+                .on_conflict(self.on_conflict)
+                .try_build()?
+        }
+    }
+
+    /// Build the statement that adds the descriptors of the nested metrics to the descriptors with
+    /// the scope in `scope`.
+    fn build_scoped_descriptors(&self) -> TokenStream {
+        let ty = &self.ty;
+
+        let scope = match &self.scope {
+            Some(scope) => quote! { &format!("{}{}{}", scope, #DEFAULT_SEPARATOR, #scope) },
+            None => quote! { scope },
+        };
+
+        quote! {
+            descriptors.extend(<#ty as prometric::MetricsGroup>::descriptors_in(#scope));
+        }
+    }
+
+    fn build_accessor(&self, vis: &syn::Visibility) -> TokenStream {
+        let ident = &self.identifier;
+        let ty = &self.ty;
+        let doc = format!("Accessor for the nested `{ident}` metrics.");
+
+        quote! {
+            #[doc = #doc]
+            #vis fn #ident(&self) -> &#ty {
+                &self.#ident
+            }
+        }
+    }
+}
+
+/// A builder for non-metric fields, marked with `#[metric(skip)]`. The metrics builder gets a
+/// `with_<field>` setter for them, and falls back to the default value if it isn't called.
+struct SkippedBuilder {
+    identifier: Ident,
+    ty: Type,
+    /// The expression to initialize the field with if it isn't set. If not set, `Default` is used.
+    default: Option<syn::Expr>,
+}
+
+impl SkippedBuilder {
+    /// The names of the fields of the metrics builder, which can't be used for skipped fields.
+    const RESERVED: [&str; 5] = ["registry", "labels", "scope", "on_conflict", "buckets"];
+
+    /// The methods of the metrics builder, which the setters of skipped fields can't shadow.
+    const METHODS: [&str; 8] = [
+        "with_registry",
+        "with_label",
+        "with_labels",
+        "with_scope",
+        "on_conflict",
+        "with_buckets",
+        "build",
+        "try_build",
+    ];
+
+    fn try_from(field: &Field, metric_field: MetricField) -> Result<Self> {
+        let MetricField {
+            ident,
+            ty,
+            rename,
+            labels,
+            help,
+            buckets,
+            quantiles,
+            max_age,
+            sample,
+            unit,
+            max_cardinality,
+            overflow,
+            ttl,
+            flatten,
+            scope,
+            skip: _,
+            default,
+        } = metric_field;
+
+        if rename.is_some()
+            || labels.is_some()
+            || help.is_some()
+            || buckets.is_some()
+            || quantiles.is_some()
+            || max_age.is_some()
+            || sample.is_some()
+            || unit.is_some()
+            || max_cardinality.is_some()
+            || overflow.is_some()
+            || ttl.is_some()
+            || flatten
+            || scope.is_some()
+        {
+            return Err(syn::Error::new_spanned(
+                field,
+                "Skipped fields only support the `default` attribute",
+            ));
+        }
+
+        let identifier = ident.ok_or(syn::Error::new_spanned(field, "Expected an identifier"))?;
+        if Self::RESERVED.iter().any(|reserved| identifier == reserved) {
+            return Err(syn::Error::new_spanned(
+                &identifier,
+                format!("`{identifier}` is reserved by the metrics builder and can't be skipped"),
+            ));
+        }
+
+        let setter = format!("with_{identifier}");
+        if Self::METHODS.contains(&setter.as_str()) {
+            return Err(syn::Error::new_spanned(
+                &identifier,
+                format!(
+                    "`{identifier}` can't be skipped, because its setter `{setter}` collides with a \
+                    method of the metrics builder"
+                ),
+            ));
+        }
+
+        Ok(Self { identifier, ty, default })
+    }
+
+    /// The field of the metrics builder that holds the value until the metrics are built.
+    fn build_builder_field(&self) -> TokenStream {
+        let ident = &self.identifier;
+        let ty = &self.ty;
+
+        quote! { #ident: Option<#ty> }
+    }
+
+    fn build_setter(&self, vis: &syn::Visibility) -> TokenStream {
+        let ident = &self.identifier;
+        let ty = &self.ty;
+        let setter = format_ident!("with_{}", ident);
+        let doc = format!("Set the `{ident}` field of the metrics struct.");
+
+        quote! {
+            #[doc = #doc]
+            #vis fn #setter(mut self, #ident: #ty) -> Self {
+                self.#ident = Some(#ident);
+                self
+            }
+        }
+    }
+
+    fn build_initializer(&self) -> TokenStream {
+        let ident = &self.identifier;
+
+        match &self.default {
+            Some(default) => quote! { #ident: self.#ident.unwrap_or_else(|| #default) },
+            None => quote! { #ident: self.#ident.unwrap_or_default() },
+        }
+    }
+}
+
 #[derive(FromField)]
 #[darling(attributes(metric))]
-#[allow(dead_code)]
 struct MetricField {
     /// The identifier of the field.
     ident: Option<Ident>,
@@ -377,15 +1470,53 @@ struct MetricField {
     ty: Type,
     /// The name override to use for the metric.
     rename: Option<String>,
-    /// The label keys to define for the metric.
-    labels: Option<Vec<LitStr>>,
+    /// The label keys to define for the metric, or a type implementing `prometric::LabelSet`. Typed
+    /// labels are rewritten to plain label keys by [`take_typed_labels`] before the field is parsed.
+    labels: Option<LabelsAttr>,
     /// The help string to use for the metric. Takes precedence over the doc attribute.
     help: Option<String>,
     /// The buckets to use for the histogram.
     buckets: Option<syn::Expr>,
-    /// The sample rate to use for the histogram.
-    /// TODO: Implement this.
+    /// The quantiles to report for the summary.
+    quantiles: Option<syn::Expr>,
+    /// The maximum age of observations for the summary, e.g. "10m".
+    max_age: Option<LitStr>,
+    /// The fraction of observations to record for histograms and summaries. The total number of
+    /// observations is tracked in a companion `<name>_observations_total` counter.
     sample: Option<LitFloat>,
+    /// The unit of the metric, e.g. "seconds" or "bytes", which is appended to the name.
+    unit: Option<LitStr>,
+    /// The maximum number of label combinations. Once the limit is reached, new label combinations
+    /// are folded into the overflow value.
+    max_cardinality: Option<LitInt>,
+    /// The label value to fold new label combinations into, defaults to "__overflow__".
+    overflow: Option<LitStr>,
+    /// The time after which series that haven't been updated are removed, e.g. "5m".
+    ttl: Option<LitStr>,
+    /// If true, the field is a nested metrics struct that shares the scope of the parent.
+    #[darling(default)]
+    flatten: bool,
+    /// The sub-scope of a nested metrics struct, which is appended to the scope of the parent.
+    scope: Option<LitStr>,
+    /// If true, the field is not a metric and is left alone.
+    #[darling(default)]
+    skip: bool,
+    /// The value of a skipped field if it isn't set on the builder. Defaults to `Default::default()`.
+    default: Option<syn::Expr>,
+}
+
+impl MetricField {
+    /// Parse the metric attribute of the field, together with its typed labels.
+    fn parse(field: &Field) -> Result<(Self, Option<Vec<Label>>)> {
+        let mut field = field.clone();
+        let typed_labels = take_typed_labels(&mut field)?;
+
+        Ok((Self::from_field(&field)?, typed_labels))
+    }
+
+    fn is_nested(&self) -> bool {
+        self.flatten || self.scope.is_some()
+    }
 }
 
 pub fn expand(metrics_attr: MetricsAttr, input: &mut ItemStruct) -> Result<TokenStream> {
@@ -393,21 +1524,105 @@ pub fn expand(metrics_attr: MetricsAttr, input: &mut ItemStruct) -> Result<Token
     let mut definitions = Vec::with_capacity(input.fields.len());
     let mut accessors = Vec::with_capacity(input.fields.len());
     let mut accessor_impls = Vec::with_capacity(input.fields.len());
+    // The statements that remove all series of the metrics, including nested metrics.
+    let mut resets = Vec::with_capacity(input.fields.len());
+    // The descriptors of the metrics with the default scope, and the statements that collect the
+    // descriptors of all metrics (including nested metrics) with another scope.
+    let mut descriptors = Vec::with_capacity(input.fields.len());
+    let mut scoped_descriptors = Vec::with_capacity(input.fields.len());
 
     // The visibility of the metrics struct
     let vis = &input.vis;
     // The identifier of the metrics struct
     let ident = &input.ident;
 
+    let scope = match &metrics_attr.scope {
+        Some(scope) if !is_valid_metric_name(&scope.value()) => {
+            return Err(syn::Error::new_spanned(
+                scope,
+                "Invalid scope. Scopes must match [a-zA-Z_:][a-zA-Z0-9_:]*",
+            ));
+        }
+        Some(scope) => scope.value(),
+        None => default_scope(&ident.to_string()),
+    };
+
+    // The series names of the metrics, to detect collisions.
+    let mut series = std::collections::HashMap::new();
+
+    // The fields, initial values and setters of the builder for skipped fields.
+    let mut builder_fields = Vec::new();
+    let mut builder_defaults = Vec::new();
+    let mut builder_setters = Vec::new();
+
+    // The names of the metric fields and histogram fields, to validate bucket overrides.
+    let mut metric_names = Vec::new();
+    let mut histogram_names = Vec::new();
+
     for field in input.fields.iter_mut() {
-        let builder =
-            MetricBuilder::try_from(field, &metrics_attr.scope.as_ref().unwrap().value())?;
+        let (metric_field, typed_labels) = MetricField::parse(field)?;
+
+        if metric_field.default.is_some() && !metric_field.skip {
+            return Err(syn::Error::new_spanned(
+                &*field,
+                "`default` is only supported for skipped fields",
+            ));
+        }
+
+        if metric_field.skip {
+            let builder = SkippedBuilder::try_from(field, metric_field)?;
 
-        initializers.push(builder.build_initializer());
-        let (definition, accessor) = builder.build_accessor(vis);
-        definitions.push(definition);
-        accessors.push(accessor);
-        accessor_impls.push(builder.build_accessor_impl(vis));
+            initializers.push(builder.build_initializer());
+            builder_fields.push(builder.build_builder_field());
+            builder_setters.push(builder.build_setter(vis));
+
+            let ident = &builder.identifier;
+            builder_defaults.push(quote! { #ident: None });
+        } else if metric_field.is_nested() {
+            let builder = NestedBuilder::try_from(field, metric_field)?;
+
+            initializers.push(builder.build_initializer());
+            accessors.push(builder.build_accessor(vis));
+
+            let ident = &builder.identifier;
+            resets.push(quote! { self.#ident.reset_all(); });
+            scoped_descriptors.push(builder.build_scoped_descriptors());
+        } else {
+            let builder = MetricBuilder::try_from(
+                field,
+                metric_field,
+                typed_labels,
+                &scope,
+                metrics_attr.counter_suffix,
+            )?;
+
+            for name in builder.series_names(&scope) {
+                if let Some(other) = series.insert(name.clone(), builder.identifier.clone()) {
+                    return Err(syn::Error::new_spanned(
+                        &builder.identifier,
+                        format!("Metric name `{name}` collides with the metric of field `{other}`"),
+                    ));
+                }
+            }
+
+            let name = builder.identifier.to_string();
+            if matches!(builder.ty, MetricType::Histogram(_)) {
+                histogram_names.push(name.clone());
+            }
+            metric_names.push(name);
+
+            initializers.push(builder.build_initializer());
+            let (definition, accessor) = builder.build_accessor(vis);
+            definitions.push(definition);
+            accessors.push(accessor);
+            accessors.push(builder.build_clear(vis));
+            accessor_impls.push(builder.build_accessor_impl(vis));
+
+            let ident = &builder.identifier;
+            resets.push(quote! { self.#ident.clear(); });
+            scoped_descriptors.push(builder.build_scoped_descriptor(descriptors.len()));
+            descriptors.push(builder.build_descriptor(&scope));
+        }
 
         // Remove the metric attribute from the field.
         field.attrs.retain(|attr| !attr.path().is_ident(METRIC_ATTR_NAME));
@@ -419,6 +1634,10 @@ pub fn expand(metrics_attr: MetricsAttr, input: &mut ItemStruct) -> Result<Token
         #vis struct #builder_name<'a> {
             registry: &'a prometheus::Registry,
             labels: std::collections::HashMap<String, String>,
+            scope: String,
+            on_conflict: prometric::ConflictPolicy,
+            buckets: std::collections::HashMap<String, Vec<f64>>,
+            #(#builder_fields),*
         }
 
         impl<'a> #builder_name<'a> {
@@ -428,17 +1647,74 @@ pub fn expand(metrics_attr: MetricsAttr, input: &mut ItemStruct) -> Result<Token
                 self
             }
 
-            /// Add a static label to the metrics struct.
+            /// Add a static label to the metrics struct. Static labels are added to every metric,
+            /// with their values bound to this instance, so that instances with different static
+            /// label values (e.g. one per shard) share the registered metrics.
             #vis fn with_label(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
                 self.labels.insert(key.into(), value.into());
                 self
             }
 
+            /// Add multiple static labels to the metrics struct.
+            #vis fn with_labels<K, V>(mut self, labels: impl IntoIterator<Item = (K, V)>) -> Self
+            where
+                K: Into<String>,
+                V: Into<String>,
+            {
+                self.labels.extend(labels.into_iter().map(|(key, value)| (key.into(), value.into())));
+                self
+            }
+
+            /// Override the scope of the metrics, which is used as a prefix for metric names.
+            #vis fn with_scope(mut self, scope: impl Into<String>) -> Self {
+                self.scope = scope.into();
+                self
+            }
+
+            /// Set what happens when the metrics are already registered with the registry and the
+            /// same static labels. Defaults to [`prometric::ConflictPolicy::Replace`].
+            #vis fn on_conflict(mut self, policy: prometric::ConflictPolicy) -> Self {
+                self.on_conflict = policy;
+                self
+            }
+
+            /// Override the buckets of the histogram field with the given name, e.g. to tune them
+            /// per environment. Building fails if the struct has no histogram field with that name,
+            /// or if the histogram is already registered with different buckets.
+            #vis fn with_buckets(mut self, field: impl Into<String>, buckets: impl Into<Vec<f64>>) -> Self {
+                self.buckets.insert(field.into(), buckets.into());
+                self
+            }
+
+            #(#builder_setters)*
+
             /// Build and register the metrics with the registry.
+            ///
+            /// # Panics
+            /// Panics if any of the metrics can't be registered. Use `try_build` to handle the
+            /// error instead.
             #vis fn build(self) -> #ident {
-                #ident {
-                    #(#initializers),*
+                self.try_build().unwrap_or_else(|e| panic!("{e}"))
+            }
+
+            /// Build and register the metrics with the registry. Returns an error if any of the
+            /// metrics can't be registered, e.g. because it conflicts with a metric that's already
+            /// registered, or if buckets are set for a field that isn't a histogram.
+            #vis fn try_build(self) -> Result<#ident, prometric::Error> {
+                const METRICS: &[&str] = &[#(#metric_names),*];
+                const HISTOGRAMS: &[&str] = &[#(#histogram_names),*];
+
+                if let Some(field) = self.buckets.keys().find(|field| !HISTOGRAMS.contains(&field.as_str())) {
+                    let reason = if METRICS.contains(&field.as_str()) { "is not a histogram" } else { "does not exist" };
+                    return Err(prometric::Error::InvalidOptions(format!(
+                        "Can't override the buckets of metric field `{field}` of `{}`: the field {reason}",
+                        stringify!(#ident),
+                    )));
                 }
+
+                Ok(#ident {
+                    #(#initializers),*
+                })
             }
         }
 
@@ -485,6 +1761,19 @@ pub fn expand(metrics_attr: MetricsAttr, input: &mut ItemStruct) -> Result<Token
 
         #(#accessor_impls)*
 
+        impl prometric::MetricsGroup for #ident {
+            const SCOPE: &'static str = #scope;
+
+            const DESCRIPTORS: &'static [prometric::MetricDescriptor] = &[#(#descriptors),*];
+
+            #[allow(unused_mut, unused_variables)]
+            fn descriptors_in(scope: &str) -> Vec<prometric::MetricDescriptor> {
+                let mut descriptors = Vec::new();
+                #(#scoped_descriptors)*
+                descriptors
+            }
+        }
+
         impl #ident {
             /// Create a new builder for the metrics struct.
             /// It will be initialized with the default registry and no labels.
@@ -492,10 +1781,20 @@ pub fn expand(metrics_attr: MetricsAttr, input: &mut ItemStruct) -> Result<Token
                 #builder_name {
                     registry: prometheus::default_registry(),
                     labels: std::collections::HashMap::new(),
+                    scope: #scope.to_owned(),
+                    on_conflict: prometric::ConflictPolicy::default(),
+                    buckets: std::collections::HashMap::new(),
+                    #(#builder_defaults),*
                 }
             }
 
             #(#accessors)*
+
+            /// Remove all series of all metrics with the static label values of this instance,
+            /// including nested metrics. Series are created again on their next update.
+            #vis fn reset_all(&self) {
+                #(#resets)*
+            }
         }
     };
 
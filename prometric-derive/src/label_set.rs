@@ -0,0 +1,70 @@
+use darling::{FromDeriveInput, FromField, ast::Data};
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{DeriveInput, Ident, Result, ext::IdentExt as _};
+
+use crate::utils::{is_reserved_label_name, is_valid_label_name};
+
+#[derive(FromDeriveInput)]
+#[darling(supports(struct_named))]
+struct LabelSetInput {
+    ident: Ident,
+    generics: syn::Generics,
+    data: Data<darling::util::Ignored, LabelSetField>,
+}
+
+#[derive(FromField)]
+struct LabelSetField {
+    /// The identifier of the field, which is used as the label name.
+    ident: Option<Ident>,
+}
+
+pub fn expand(input: &DeriveInput) -> Result<TokenStream> {
+    let input = LabelSetInput::from_derive_input(input)?;
+    let ident = &input.ident;
+
+    let fields = input.data.take_struct().expect("only named structs are supported").fields;
+    let field_idents = fields.iter().filter_map(|field| field.ident.as_ref()).collect::<Vec<_>>();
+
+    if let Some(invalid) =
+        field_idents.iter().find(|ident| !is_valid_label_name(&ident.unraw().to_string()))
+    {
+        return Err(syn::Error::new_spanned(
+            invalid,
+            format!(
+                "Invalid label name `{}`. Label names must match [a-zA-Z_][a-zA-Z0-9_]*",
+                invalid.unraw()
+            ),
+        ));
+    }
+
+    if let Some(reserved) =
+        field_idents.iter().find(|ident| is_reserved_label_name(&ident.unraw().to_string()))
+    {
+        return Err(syn::Error::new_spanned(
+            reserved,
+            format!(
+                "Label name `{}` is reserved. `le`, `quantile` and names starting with `__` can't \
+                be used",
+                reserved.unraw()
+            ),
+        ));
+    }
+
+    let names = field_idents.iter().map(|ident| ident.unraw().to_string());
+    let values = field_idents.iter().map(|ident| format_ident!("__{}", ident.unraw()));
+    let value_refs = values.clone();
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics prometric::LabelSet for #ident #ty_generics #where_clause {
+            const LABEL_NAMES: &'static [&'static str] = &[#(#names),*];
+
+            fn with_label_values<R>(&self, f: impl FnOnce(&[&str]) -> R) -> R {
+                #(let #values = prometric::LabelValue::into_label_value(&self.#field_idents);)*
+                f(&[#(#value_refs.as_ref()),*])
+            }
+        }
+    })
+}
@@ -0,0 +1,74 @@
+use darling::{FromDeriveInput, FromVariant, ast::Data};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{DeriveInput, Ident, Result};
+
+use crate::utils::to_snake_case;
+
+#[derive(FromDeriveInput)]
+#[darling(attributes(label_value), supports(struct_any, enum_unit))]
+struct LabelValueInput {
+    ident: Ident,
+    generics: syn::Generics,
+    data: Data<LabelValueVariant, darling::util::Ignored>,
+}
+
+#[derive(FromVariant)]
+#[darling(attributes(label_value))]
+struct LabelValueVariant {
+    /// The identifier of the variant.
+    ident: Ident,
+    /// The label value override to use for the variant.
+    rename: Option<String>,
+}
+
+impl LabelValueVariant {
+    /// The label value of the variant, which defaults to the snake_case variant name.
+    fn value(&self) -> String {
+        self.rename.clone().unwrap_or_else(|| to_snake_case(&self.ident.to_string()))
+    }
+}
+
+pub fn expand(input: &DeriveInput) -> Result<TokenStream> {
+    let input = LabelValueInput::from_derive_input(input)?;
+    let ident = &input.ident;
+
+    // Unit enums map to static strings, structs fall back to their `Display` implementation.
+    let conversion = match &input.data {
+        Data::Enum(variants) => {
+            let arms = variants.iter().map(|variant| {
+                let variant_ident = &variant.ident;
+                let value = variant.value();
+                quote! { #ident::#variant_ident => #value }
+            });
+
+            quote! {
+                std::borrow::Cow::Borrowed(match *self {
+                    #(#arms),*
+                })
+            }
+        }
+        Data::Struct(_) => quote! {
+            std::borrow::Cow::Owned(std::string::ToString::to_string(self))
+        },
+    };
+
+    let mut generics = input.generics.clone();
+    generics.params.insert(0, syn::parse_quote!('__label));
+    let (impl_generics, _, _) = generics.split_for_impl();
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics prometric::LabelValue<'__label> for &#ident #ty_generics #where_clause {
+            fn into_label_value(self) -> std::borrow::Cow<'__label, str> {
+                #conversion
+            }
+        }
+
+        impl #impl_generics prometric::LabelValue<'__label> for #ident #ty_generics #where_clause {
+            fn into_label_value(self) -> std::borrow::Cow<'__label, str> {
+                prometric::LabelValue::into_label_value(&self)
+            }
+        }
+    })
+}
@@ -1,11 +1,13 @@
 //! This crate contains the attribute macro for generating Prometheus metrics.
 //! Refer to the [metrics] attribute documentation for more information.
 use proc_macro::TokenStream;
-use syn::{ItemStruct, parse_macro_input};
+use syn::{DeriveInput, ItemStruct, parse_macro_input};
 
 use crate::expand::MetricsAttr;
 
 mod expand;
+mod label_set;
+mod label_value;
 mod utils;
 
 /// This attribute macro instruments all of the struct fields with Prometheus metrics according to
@@ -14,8 +16,18 @@ mod utils;
 ///
 /// # Attributes
 ///
-/// - `scope`: Sets the prefix for metric names (required)
+/// - `scope`: Sets the prefix for metric names. Defaults to the snake_case struct name without the
+///   `Metrics` suffix, e.g. `app` for `AppMetrics`.
 /// - `static`: If enabled, generates a static `LazyLock` with a SCREAMING_SNAKE_CASE name.
+/// - `counter_suffix`: How the `_total` suffix of counter names is handled. `"off"` (default) uses
+///   the names as is, `"auto"` appends `_total` when it's missing, and `"require"` rejects counter
+///   names without it at compile time.
+///
+/// Metric names, label names and the scope are validated at compile time, and metrics in the same
+/// struct can't expose the same series (including the `_bucket`, `_sum` and `_count` series of
+/// histograms and summaries). When the metrics are built, they are also checked against the other
+/// metrics in the registry: registering a name that's already registered with a different type,
+/// help or labels fails with a message naming both definitions.
 ///
 /// # Example
 /// ```rust
@@ -23,10 +35,11 @@ mod utils;
 /// use prometric::{Counter, Gauge, Histogram};
 ///
 /// // The `scope` attribute is used to set the prefix for the metric names in this struct.
-/// #[metrics(scope = "app")]
+/// #[metrics(scope = "app", counter_suffix = "auto")]
 /// struct AppMetrics {
+///     // With `counter_suffix = "auto"`, `_total` is appended to counter names (`app_http_requests_total` here).
 ///     /// The total number of HTTP requests.
-///     #[metric(rename = "http_requests_total", labels = ["method", "path"])]
+///     #[metric(labels = ["method", "path"])]
 ///     http_requests: Counter,
 ///
 ///     // For histograms, the `buckets` attribute is optional. It will default to [prometheus::DEFAULT_BUCKETS] if not provided.
@@ -70,9 +83,9 @@ mod utils;
 /// # TYPE app_current_active_users gauge
 /// app_current_active_users{host="localhost",port="8080",service="service-1"} 20
 ///
-/// # HELP app_errors The total number of errors.
-/// # TYPE app_errors counter
-/// app_errors{host="localhost",port="8080"} 1
+/// # HELP app_errors_total The total number of errors.
+/// # TYPE app_errors_total counter
+/// app_errors_total{host="localhost",port="8080"} 1
 ///
 /// # HELP app_http_requests_duration The duration of HTTP requests.
 /// # TYPE app_http_requests_duration histogram
@@ -95,6 +108,527 @@ mod utils;
 /// app_http_requests_total{host="localhost",method="GET",path="/",port="8080"} 2
 /// app_http_requests_total{host="localhost",method="POST",path="/",port="8080"} 2
 /// ```
+/// # Summary Example
+///
+/// Summaries report client-side quantiles over a sliding time window. The `quantiles` attribute
+/// defaults to `prometric::DEFAULT_QUANTILES` and `max_age` (the size of the window) defaults to
+/// `prometric::DEFAULT_MAX_AGE`.
+///
+/// ```rust
+/// use prometric::Summary;
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "db")]
+/// struct DbMetrics {
+///     /// The latency of database queries.
+///     #[metric(labels = ["query"], quantiles = [0.5, 0.9, 0.99], max_age = "10m")]
+///     query_latency: Summary,
+/// }
+///
+/// let metrics = DbMetrics::default();
+/// metrics.query_latency("select").observe(0.042);
+/// ```
+///
+/// # Histogram Buckets
+///
+/// Besides a literal array or an expression, the `buckets` attribute accepts the helpers of
+/// `prometric::buckets` without their path, with the number of buckets as the last argument. Literal
+/// buckets are checked to be finite and strictly increasing at compile time.
+///
+/// ```rust
+/// use prometric::Histogram;
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The duration of requests: 1ms, 2ms, 4ms, ..., 16.384s.
+///     #[metric(buckets = exponential(0.001, 2.0, 15))]
+///     request_duration: Histogram,
+///
+///     /// The size of batches: 10, 20, ..., 100.
+///     #[metric(buckets = linear(10.0, 10.0, 10))]
+///     batch_size: Histogram,
+///
+///     /// The duration of queries: 10 buckets from 1ms to 10s.
+///     #[metric(buckets = exponential_range(0.001, 10.0, 10))]
+///     query_duration: Histogram,
+///
+///     /// The duration of jobs.
+///     #[metric(buckets = duration_seconds_default())]
+///     job_duration: Histogram,
+/// }
+///
+/// // Buckets can be overridden when building the metrics, e.g. from configuration. Building
+/// // fails if the field doesn't exist or isn't a histogram.
+/// let metrics = AppMetrics::builder().with_buckets("request_duration", [0.1, 1.0, 10.0]).build();
+/// ```
+///
+/// # Typed Labels
+///
+/// Accessor arguments accept any `prometric::LabelValue`, which is implemented for strings,
+/// integers and `bool`. Labels can also be declared with a type, in which case the accessor
+/// argument has that type. Enums can derive [`LabelValue`](derive@LabelValue) to map their variants
+/// to snake_case label values:
+///
+/// ```rust
+/// use prometric::Counter;
+/// use prometric_derive::{LabelValue, metrics};
+///
+/// #[derive(Clone, Copy, LabelValue)]
+/// enum HttpMethod {
+///     Get,
+///     Post,
+///     #[label_value(rename = "delete")]
+///     Remove,
+/// }
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The total number of HTTP requests.
+///     #[metric(labels = [method: HttpMethod, status: u16, "path"])]
+///     http_requests: Counter,
+/// }
+///
+/// let metrics = AppMetrics::default();
+///
+/// // Recorded with method="get", status="200" and path="/".
+/// metrics.http_requests(HttpMethod::Get, 200, "/").inc();
+/// ```
+///
+/// Metrics with many labels can take a single struct that derives [`LabelSet`](derive@LabelSet)
+/// instead, which rules out mixing up the order of the labels. The field names are used as the
+/// label names:
+///
+/// ```rust
+/// use prometric::{Counter, Histogram};
+/// use prometric_derive::{LabelSet, metrics};
+///
+/// #[derive(LabelSet)]
+/// struct RequestLabels {
+///     method: &'static str,
+///     path: String,
+///     status: u16,
+/// }
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The total number of HTTP requests.
+///     #[metric(labels = RequestLabels)]
+///     http_requests: Counter,
+///
+///     /// The duration of HTTP requests.
+///     #[metric(labels = RequestLabels)]
+///     http_requests_duration: Histogram,
+/// }
+///
+/// let metrics = AppMetrics::default();
+///
+/// let labels = RequestLabels { method: "GET", path: "/".to_owned(), status: 200 };
+/// metrics.http_requests(&labels).inc();
+/// metrics.http_requests_duration(&labels).observe(0.1);
+/// ```
+///
+/// # Handles
+///
+/// Every accessor call hashes its labels and looks up the matching series. For hot loops, `bind`
+/// resolves the series once and returns an owned handle that is `Clone + Send + Sync`:
+///
+/// ```rust
+/// use prometric::Counter;
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The number of processed messages.
+///     #[metric(labels = ["topic"])]
+///     messages: Counter,
+/// }
+///
+/// let metrics = AppMetrics::default();
+///
+/// let messages = metrics.messages("orders").bind();
+/// for _ in 0..1000 {
+///     messages.inc();
+/// }
+/// ```
+///
+/// # Reading Values
+///
+/// Counter and gauge accessors and handles return the current value with `get()`, and histogram
+/// accessors and handles return the count, sum and cumulative bucket counts with `snapshot()`.
+/// This is useful in tests and for in-process decisions such as backpressure. Accessors read
+/// without creating the series, so reading a series that doesn't exist returns zero or an empty
+/// snapshot, and doesn't count against the cardinality limit or refresh the TTL. To do so, they
+/// scan all series of the metric, so for frequent reads (e.g. on hot paths) bind a handle once and
+/// read it with `bind().get()`:
+///
+/// ```rust
+/// use prometric::{Gauge, Histogram};
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The number of queued messages.
+///     #[metric]
+///     queue_depth: Gauge,
+///
+///     /// The size of processed messages.
+///     #[metric(buckets = [256.0, 1024.0])]
+///     message_size: Histogram,
+/// }
+///
+/// let metrics = AppMetrics::default();
+/// metrics.queue_depth().set(10);
+/// metrics.message_size().observe(512);
+///
+/// assert_eq!(metrics.queue_depth().get(), 10);
+///
+/// let snapshot = metrics.message_size().snapshot();
+/// assert_eq!(snapshot.count, 1);
+/// assert_eq!(snapshot.buckets, [(256.0, 0), (1024.0, 1)]);
+/// ```
+///
+/// # Timing
+///
+/// Histogram and summary accessors can time code directly, recording the elapsed time in seconds:
+///
+/// ```rust
+/// use prometric::Histogram;
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The duration of requests.
+///     #[metric(labels = ["method"])]
+///     request_duration: Histogram,
+/// }
+///
+/// let metrics = AppMetrics::default();
+///
+/// // Records the elapsed time when the guard is dropped. Use `stop_and_discard` to skip recording.
+/// let timer = metrics.request_duration("GET").start_timer();
+/// drop(timer);
+///
+/// // Times a closure.
+/// let value = metrics.request_duration("GET").time(|| 42);
+///
+/// // Times a future from its first poll until its completion.
+/// let future = metrics.request_duration("GET").time_future(async { 42 });
+/// ```
+///
+/// # Tracking In-Flight Operations
+///
+/// Gauge accessors can track in-flight operations with a guard that increments the gauge and
+/// decrements it again when dropped, so that early returns don't leave the gauge skewed:
+///
+/// ```rust
+/// use prometric::Gauge;
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The number of requests in progress.
+///     #[metric(labels = ["method"])]
+///     requests_in_progress: Gauge,
+/// }
+///
+/// let metrics = AppMetrics::default();
+///
+/// // Incremented now, decremented when the guard is dropped.
+/// let _guard = metrics.requests_in_progress("GET").track_inflight();
+///
+/// // Incremented until the future completes or is dropped.
+/// let future = metrics.requests_in_progress("GET").track_future(async { 42 });
+/// ```
+///
+/// # Sampling
+///
+/// Histograms and summaries on very hot paths can record only a fraction of their observations
+/// with the `sample` attribute. The total number of observations is still counted exactly in a
+/// companion `<name>_observations_total` counter.
+///
+/// ```rust
+/// use prometric::Histogram;
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The size of processed messages. Only 10% of the messages are recorded.
+///     #[metric(sample = 0.1)]
+///     message_size: Histogram,
+/// }
+///
+/// let metrics = AppMetrics::default();
+/// metrics.message_size().observe(1024);
+/// ```
+///
+/// # Cardinality Limits
+///
+/// Labels with unbounded values (e.g. paths or user IDs) can be limited with the `max_cardinality`
+/// attribute. Once the metric has that many label combinations, new combinations are folded into
+/// the `overflow` value (`"__overflow__"` by default), and the
+/// `prometric_cardinality_limit_hits_total{metric="..."}` counter is incremented. Static labels
+/// keep their values.
+///
+/// ```rust
+/// use prometric::Counter;
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The total number of HTTP requests.
+///     #[metric(labels = ["path"], max_cardinality = 1000, overflow = "other")]
+///     http_requests: Counter,
+/// }
+///
+/// let metrics = AppMetrics::default();
+/// metrics.http_requests("/").inc();
+/// ```
+///
+/// # Expiring and Removing Series
+///
+/// Series for label values that go away (e.g. disconnected peers) can be removed with the `ttl`
+/// attribute. Series that haven't been updated within the TTL are removed when the metric is
+/// collected, and are created again on their next update. Handles returned by `bind` only refresh
+/// the series when they're created.
+///
+/// ```rust
+/// use prometric::Gauge;
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "p2p")]
+/// struct PeerMetrics {
+///     /// The score of each connected peer.
+///     #[metric(labels = ["peer"], ttl = "5m")]
+///     peer_score: Gauge,
+/// }
+///
+/// let metrics = PeerMetrics::default();
+/// metrics.peer_score("peer-1").set(10);
+/// ```
+///
+/// Series can also be removed explicitly: `remove()` on an accessor removes the series with its
+/// label values, `clear_<field>()` removes all series of a metric, and `reset_all()` removes all
+/// series of all metrics (including nested metrics). Only the series with the static label values
+/// of the instance are removed.
+///
+/// ```rust
+/// # use prometric::Gauge;
+/// # use prometric_derive::metrics;
+/// #
+/// # #[metrics(scope = "p2p")]
+/// # struct PeerMetrics {
+/// #     /// The score of each connected peer.
+/// #     #[metric(labels = ["peer"])]
+/// #     peer_score: Gauge,
+/// # }
+/// #
+/// let metrics = PeerMetrics::builder().with_label("tenant", "a").build();
+/// metrics.peer_score("peer-1").set(10);
+///
+/// // The peer disconnected.
+/// metrics.peer_score("peer-1").remove();
+///
+/// // The tenant went away.
+/// metrics.reset_all();
+/// ```
+///
+/// # Units
+///
+/// The `unit` attribute appends the unit to the metric name (before the `_total` suffix of
+/// counters), unless the name already ends with it. The unit is exported in the `# UNIT` line when
+/// the exporter serves the OpenMetrics format. Histograms and summaries in `seconds` also accept a
+/// `Duration` in `observe`. Handles returned by `bind` don't know the unit, so they accept a
+/// `Duration` for any histogram or summary and record it in seconds:
+///
+/// ```rust
+/// use prometric::{Counter, Histogram};
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The duration of requests. Exported as `app_request_duration_seconds`.
+///     #[metric(unit = "seconds")]
+///     request_duration: Histogram,
+///
+///     /// The number of bytes received. Exported as `app_received_bytes_total`.
+///     #[metric(rename = "received_total", unit = "bytes")]
+///     received: Counter,
+/// }
+///
+/// let metrics = AppMetrics::default();
+/// metrics.request_duration().observe(std::time::Duration::from_millis(20));
+/// metrics.request_duration().bind().observe(std::time::Duration::from_millis(30));
+/// metrics.received().inc_by(1024u64);
+/// ```
+///
+/// # Nested Metrics
+///
+/// Fields can be other `#[metrics]` structs, which makes it possible to compose reusable groups of
+/// metrics. The parent builder forwards its registry and static labels to the nested builder. With
+/// `#[metric(flatten)]` the nested metrics share the scope of the parent, and with
+/// `#[metric(scope = "...")]` the sub-scope is appended to it:
+///
+/// ```rust
+/// use prometric::{Counter, Gauge};
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "rpc")]
+/// struct RpcMetrics {
+///     /// The total number of RPC calls.
+///     #[metric(labels = ["method"])]
+///     calls: Counter,
+/// }
+///
+/// #[metrics(scope = "server")]
+/// struct ServerMetrics {
+///     /// The number of open connections.
+///     #[metric]
+///     connections: Gauge,
+///
+///     /// Registered as `server_calls`.
+///     #[metric(flatten)]
+///     rpc: RpcMetrics,
+///
+///     /// Registered as `server_admin_calls`.
+///     #[metric(scope = "admin")]
+///     admin: RpcMetrics,
+/// }
+///
+/// let metrics = ServerMetrics::default();
+/// metrics.rpc().calls("eth_call").inc();
+/// metrics.admin().calls("admin_peers").inc();
+/// ```
+///
+/// # Non-Metric Fields
+///
+/// Fields that aren't metrics can be kept in the struct with `#[metric(skip)]`. The builder gets a
+/// `with_<field>` setter for them, and falls back to `Default::default()` (or the `default`
+/// expression, if provided) when the setter isn't called:
+///
+/// ```rust
+/// use prometric::Counter;
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The total number of requests.
+///     #[metric]
+///     requests: Counter,
+///
+///     #[metric(skip)]
+///     service: String,
+///
+///     #[metric(skip, default = std::time::Instant::now())]
+///     started: std::time::Instant,
+/// }
+///
+/// let metrics = AppMetrics::builder().with_service("api".to_owned()).build();
+/// assert_eq!(metrics.service, "api");
+/// ```
+///
+/// # Multiple Instances
+///
+/// Static labels set with `with_label` are added as labels to every metric, with their values bound
+/// to the instance. Instances with different static label values share the registered metrics and
+/// update their own series, so a struct can be built once per shard, peer, etc.:
+///
+/// ```rust
+/// use prometric::Counter;
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "shard")]
+/// struct ShardMetrics {
+///     /// The total number of processed messages.
+///     #[metric]
+///     messages: Counter,
+/// }
+///
+/// let registry = prometheus::Registry::new();
+/// let shards = (0..2)
+///     .map(|i| ShardMetrics::builder().with_registry(&registry).with_label("shard", i.to_string()).build())
+///     .collect::<Vec<_>>();
+///
+/// shards[0].messages().inc();
+/// shards[1].messages().inc_by(2u64);
+///
+/// let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+/// assert!(output.contains(r#"shard_messages{shard="0"} 1"#));
+/// assert!(output.contains(r#"shard_messages{shard="1"} 2"#));
+/// ```
+///
+/// # Registration Conflicts
+///
+/// `build()` panics if the metrics can't be registered, while `try_build()` returns a
+/// `prometric::Error` instead. Building a struct more than once with the same registry and static
+/// labels registers the same instance of the metrics again; what happens then is set with
+/// `on_conflict`:
+///
+/// - `ConflictPolicy::Replace` (default): the existing instance is replaced, so its series are no
+///   longer exported.
+/// - `ConflictPolicy::Reuse`: the existing instance is reused, so both instances update the same
+///   series.
+/// - `ConflictPolicy::Error`: building fails with `Error::AlreadyRegistered`.
+///
+/// Instances with different static labels always share the registered metrics. Registering a
+/// metric with a different type, help, labels, unit or options (e.g. histogram buckets) than the
+/// registered one always fails with `Error::Conflict`.
+///
+/// ```rust
+/// use prometric::{ConflictPolicy, Counter, Error};
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The total number of requests.
+///     #[metric]
+///     requests: Counter,
+/// }
+///
+/// let registry = prometheus::Registry::new();
+/// let first = AppMetrics::builder().with_registry(&registry).try_build().unwrap();
+/// let second = AppMetrics::builder()
+///     .with_registry(&registry)
+///     .on_conflict(ConflictPolicy::Reuse)
+///     .try_build()
+///     .unwrap();
+/// first.requests().inc();
+/// second.requests().inc();
+///
+/// let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+/// assert!(output.contains("app_requests 2"));
+///
+/// let err = AppMetrics::builder()
+///     .with_registry(&registry)
+///     .on_conflict(ConflictPolicy::Error)
+///     .try_build();
+/// assert!(matches!(err, Err(Error::AlreadyRegistered(_))));
+/// ```
+///
+/// # Descriptors
+///
+/// Every struct implements `prometric::MetricsGroup`, which describes its metrics without
+/// registering them: the name, type, help, labels, unit and (if known at compile time) histogram
+/// buckets. `DESCRIPTORS` holds the metrics of the struct itself, while `descriptors()` also
+/// includes nested metrics. This is useful to generate documentation or validate metric names:
+///
+/// ```rust
+/// use prometric::{Counter, MetricsGroup};
+/// use prometric_derive::metrics;
+///
+/// #[metrics(scope = "app")]
+/// struct AppMetrics {
+///     /// The total number of requests.
+///     #[metric(labels = ["method"])]
+///     requests: Counter,
+/// }
+///
+/// for descriptor in AppMetrics::descriptors() {
+///     assert_eq!(descriptor.name, "app_requests");
+///     assert_eq!(descriptor.labels, ["method"]);
+/// }
+/// ```
+///
 /// # Static Metrics Example
 ///
 /// When the `static` attribute is enabled, a static `LazyLock` is generated with a
@@ -128,7 +662,7 @@ mod utils;
 /// ```
 ///
 /// # Exporting Metrics
-/// An HTTP exporter is provided by [`prometric::exporter::ExporterBuilder`]. Usage:
+/// An HTTP exporter is provided by `prometric::exporter::ExporterBuilder`. Usage:
 ///
 /// ```rust
 /// use prometric::exporter::ExporterBuilder;
@@ -225,3 +759,52 @@ pub fn metrics(attr: TokenStream, item: TokenStream) -> TokenStream {
 
     expand::expand(attributes, &mut input).unwrap_or_else(|err| err.into_compile_error()).into()
 }
+
+/// Derive `prometric::LabelValue` for a type, so that it can be used as a label value in the
+/// generated accessors.
+///
+/// Unit enum variants map to their snake_case name, which can be overridden with
+/// `#[label_value(rename = "...")]`. Structs are converted with their `Display` implementation.
+///
+/// ```rust
+/// use prometric_derive::LabelValue;
+///
+/// #[derive(LabelValue)]
+/// enum Status {
+///     /// Converted to "in_progress".
+///     InProgress,
+///     /// Converted to "ok".
+///     #[label_value(rename = "ok")]
+///     Success,
+/// }
+/// ```
+#[proc_macro_derive(LabelValue, attributes(label_value))]
+pub fn derive_label_value(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+
+    label_value::expand(&input).unwrap_or_else(|err| err.into_compile_error()).into()
+}
+
+/// Derive `prometric::LabelSet` for a struct with named fields, so that it can be used as the
+/// labels of a metric with `#[metric(labels = MyLabels)]`.
+///
+/// The field names are used as the label names, and the field values as the label values. Field
+/// types must implement `prometric::LabelValue` by reference, which includes strings, integers,
+/// `bool` and types that derive [`LabelValue`](derive@LabelValue).
+///
+/// ```rust
+/// use prometric_derive::LabelSet;
+///
+/// #[derive(LabelSet)]
+/// struct RequestLabels {
+///     method: &'static str,
+///     path: String,
+///     status: u16,
+/// }
+/// ```
+#[proc_macro_derive(LabelSet)]
+pub fn derive_label_set(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+
+    label_set::expand(&input).unwrap_or_else(|err| err.into_compile_error()).into()
+}
@@ -41,3 +41,147 @@ pub(crate) fn to_screaming_snake(s: &str) -> String {
 
     result
 }
+
+/// Parse a human-readable duration such as `"10m"`, `"1h30m"` or `"500ms"`.
+///
+/// Supported units are `ms`, `s`, `m`, `h` and `d`. Returns `None` if the string is malformed.
+pub(crate) fn parse_duration(s: &str) -> Option<std::time::Duration> {
+    let mut total = std::time::Duration::ZERO;
+    let mut rest = s.trim();
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if digits == 0 {
+            return None;
+        }
+
+        let value: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+
+        let unit = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let millis = match &rest[..unit] {
+            "ms" => 1,
+            "s" => 1_000,
+            "m" => 60 * 1_000,
+            "h" => 60 * 60 * 1_000,
+            "d" => 24 * 60 * 60 * 1_000,
+            _ => return None,
+        };
+        rest = &rest[unit..];
+
+        total += std::time::Duration::from_millis(value.checked_mul(millis)?);
+    }
+
+    Some(total)
+}
+
+/// Convert a PascalCase string to snake_case.
+pub(crate) fn to_snake_case(s: &str) -> String {
+    to_screaming_snake(s).to_ascii_lowercase()
+}
+
+/// Returns true if the name is a valid Prometheus metric name, i.e. it matches
+/// `[a-zA-Z_:][a-zA-Z0-9_:]*`.
+pub(crate) fn is_valid_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Returns true if the name is a valid Prometheus label name, i.e. it matches
+/// `[a-zA-Z_][a-zA-Z0-9_]*`.
+pub(crate) fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns true if the label name is reserved, either by the histogram (`le`) and summary
+/// (`quantile`) types or for internal use (`__` prefix).
+pub(crate) fn is_reserved_label_name(name: &str) -> bool {
+    name == "le" || name == "quantile" || name.starts_with("__")
+}
+
+/// The default scope for a metrics struct, which is the snake_case struct name without the
+/// `Metrics` suffix, e.g. `app` for `AppMetrics`.
+pub(crate) fn default_scope(struct_name: &str) -> String {
+    let scope = to_snake_case(struct_name);
+    match scope.strip_suffix("_metrics") {
+        Some(stripped) if !stripped.is_empty() => stripped.to_owned(),
+        _ => scope,
+    }
+}
+
+/// Returns true if the unit is valid, i.e. it's a non-empty lowercase name like `seconds` or
+/// `bytes` that can be used as a metric name suffix.
+pub(crate) fn is_valid_unit(unit: &str) -> bool {
+    let mut chars = unit.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase())
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Append the unit suffix to the metric name, unless the name already ends with it. For counters,
+/// the unit is inserted before an existing `_total` suffix, e.g. `cpu_seconds_total`.
+pub(crate) fn with_unit_suffix(name: &str, unit: &str, is_counter: bool) -> String {
+    let (base, total) = match name.strip_suffix("_total") {
+        Some(base) if is_counter => (base, "_total"),
+        _ => (name, ""),
+    };
+
+    let suffix = format!("_{unit}");
+    if base.ends_with(&suffix) || base == unit {
+        name.to_owned()
+    } else {
+        format!("{base}{suffix}{total}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names() {
+        assert!(is_valid_metric_name("app_http_requests_total"));
+        assert!(is_valid_metric_name("app:requests"));
+        assert!(!is_valid_metric_name("1app"));
+        assert!(!is_valid_metric_name("app-requests"));
+        assert!(!is_valid_metric_name(""));
+
+        assert!(is_valid_label_name("_method"));
+        assert!(!is_valid_label_name("method:name"));
+        assert!(!is_valid_label_name("http-method"));
+
+        assert!(is_reserved_label_name("le"));
+        assert!(is_reserved_label_name("quantile"));
+        assert!(is_reserved_label_name("__name__"));
+        assert!(!is_reserved_label_name("method"));
+    }
+
+    #[test]
+    fn test_default_scope() {
+        assert_eq!(default_scope("AppMetrics"), "app");
+        assert_eq!(default_scope("HttpServerMetrics"), "http_server");
+        assert_eq!(default_scope("Metrics"), "metrics");
+        assert_eq!(default_scope("Rpc"), "rpc");
+    }
+
+    #[test]
+    fn test_units() {
+        assert!(is_valid_unit("seconds"));
+        assert!(is_valid_unit("kilo_bytes"));
+        assert!(!is_valid_unit("Seconds"));
+        assert!(!is_valid_unit("_seconds"));
+        assert!(!is_valid_unit(""));
+
+        assert_eq!(with_unit_suffix("duration", "seconds", false), "duration_seconds");
+        assert_eq!(with_unit_suffix("duration_seconds", "seconds", false), "duration_seconds");
+        assert_eq!(with_unit_suffix("cpu_total", "seconds", true), "cpu_seconds_total");
+        assert_eq!(with_unit_suffix("cpu_seconds_total", "seconds", true), "cpu_seconds_total");
+        assert_eq!(with_unit_suffix("size_total", "bytes", false), "size_total_bytes");
+    }
+}
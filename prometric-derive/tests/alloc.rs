@@ -0,0 +1,108 @@
+//! Verifies that updating metrics through the generated accessors doesn't allocate when the label
+//! values are static strings.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use prometric::{Counter, Gauge, Histogram, Summary};
+
+/// An allocator that counts the allocations made by the current thread.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[prometric_derive::metrics(scope = "alloc")]
+struct AllocMetrics {
+    /// Test counter metric.
+    #[metric(labels = ["method", "path"])]
+    requests: Counter,
+
+    /// Test gauge metric.
+    #[metric(labels = ["method"])]
+    active: Gauge,
+
+    /// Test histogram metric.
+    #[metric(labels = ["method"])]
+    duration: Histogram,
+
+    /// Test summary metric.
+    #[metric(labels = ["method"])]
+    latency: Summary,
+}
+
+#[test]
+fn static_labels_do_not_allocate() {
+    let registry = prometheus::Registry::new();
+    let metrics = AllocMetrics::builder().with_registry(&registry).build();
+
+    let update = || {
+        metrics.requests("GET", "/").inc();
+        metrics.requests("GET", "/").inc_by(2);
+        metrics.active("GET").set(3);
+        metrics.duration("GET").observe(0.5);
+        metrics.latency("GET").observe(0.5);
+    };
+
+    // The first update creates the series, which allocates.
+    update();
+
+    assert_eq!(allocations(|| (0..100).for_each(|_| update())), 0);
+}
+
+#[test]
+fn builder_labels_do_not_allocate() {
+    let registry = prometheus::Registry::new();
+    let metrics = AllocMetrics::builder()
+        .with_registry(&registry)
+        .with_label("host", "x")
+        .with_label("shard", "0")
+        .build();
+
+    let update = || {
+        metrics.requests("GET", "/").inc();
+        metrics.active("GET").set(3);
+        metrics.duration("GET").observe(0.5);
+        metrics.latency("GET").observe(0.5);
+    };
+
+    update();
+
+    assert_eq!(allocations(|| (0..100).for_each(|_| update())), 0);
+}
+
+#[test]
+fn owned_labels_are_not_copied() {
+    let registry = prometheus::Registry::new();
+    let metrics = AllocMetrics::builder().with_registry(&registry).build();
+
+    let method = String::from("GET");
+    metrics.active(method.clone()).inc();
+
+    // Only the `clone` allocates, the owned value is moved into the accessor.
+    assert_eq!(allocations(|| metrics.active(method.clone()).inc()), 1);
+    assert_eq!(allocations(|| metrics.active(method.as_str()).inc()), 0);
+}
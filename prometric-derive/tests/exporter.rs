@@ -1,8 +1,8 @@
-use prometric::{Counter, exporter::ExporterBuilder};
+use prometric::{Counter, Histogram, exporter::ExporterBuilder};
 use prometric_derive::metrics;
 
 use http_body_util::{BodyExt, Empty};
-use hyper::body::Bytes;
+use hyper::{Request, body::Bytes, header::ACCEPT};
 use hyper_util::{client::legacy::Client, rt::TokioExecutor};
 
 #[metrics(scope = "test")]
@@ -83,3 +83,45 @@ async fn test_exporter_async() {
     // Verify the counter value is 2 (incremented twice)
     assert!(body.contains("app_test_counter 2"));
 }
+
+#[tokio::test]
+async fn test_exporter_openmetrics() {
+    #[metrics(scope = "test_openmetrics")]
+    struct TestMetrics {
+        /// Test metric in seconds.
+        #[metric(unit = "seconds")]
+        duration: Histogram,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = TestMetrics::builder().with_registry(&registry).build();
+    metrics.duration().observe(std::time::Duration::from_secs(1));
+
+    ExporterBuilder::new()
+        .with_address("127.0.0.1:9092")
+        .with_registry(registry)
+        .install()
+        .unwrap();
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+
+    let request = Request::get("http://127.0.0.1:9092/metrics")
+        .header(ACCEPT, "application/openmetrics-text; version=1.0.0")
+        .body(Empty::new())
+        .unwrap();
+    let response = client.request(request).await.expect("Failed to make request");
+
+    assert_eq!(response.status(), 200);
+    let content_type = response.headers()["content-type"].to_str().unwrap();
+    assert!(content_type.starts_with("application/openmetrics-text"));
+
+    let body_bytes =
+        response.into_body().collect().await.expect("Failed to read response body").to_bytes();
+    let body = String::from_utf8(body_bytes.to_vec()).expect("Invalid UTF-8");
+
+    assert!(body.contains("# UNIT test_openmetrics_duration_seconds seconds"));
+    assert!(body.contains("test_openmetrics_duration_seconds_count 1"));
+    assert!(body.ends_with("# EOF\n"));
+}
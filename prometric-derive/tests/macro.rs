@@ -1,7 +1,7 @@
 use std::time::Duration;
 
 use prometheus::Encoder as _;
-use prometric::{Counter, Gauge, Histogram};
+use prometric::{Counter, Gauge, Histogram, Summary};
 
 /// This is a struct that contains the metrics for the application.
 ///
@@ -193,3 +193,1052 @@ fn bucket_expressions_work() {
 
     assert!(output.contains("test_hist"));
 }
+
+#[test]
+fn summary_quantiles_work() {
+    #[prometric_derive::metrics(scope = "test")]
+    struct SummaryMetrics {
+        /// Test summary metric.
+        #[metric(labels = ["method"], quantiles = [0.5, 0.9, 0.99], max_age = "10m")]
+        latency: Summary,
+
+        /// Test summary metric with default quantiles.
+        #[metric]
+        default_latency: Summary,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = SummaryMetrics::builder().with_registry(&registry).build();
+
+    for i in 1..=100 {
+        metrics.latency("GET").observe(i);
+    }
+    metrics.default_latency().observe(Duration::from_millis(10).as_secs_f64());
+
+    let encoder = prometheus::TextEncoder::new();
+    let output = encoder.encode_to_string(&registry.gather()).unwrap();
+
+    assert!(output.contains("# TYPE test_latency summary"));
+    assert!(output.contains(r#"test_latency{method="GET",quantile="0.5"} 50"#));
+    assert!(output.contains(r#"test_latency{method="GET",quantile="0.99"} 99"#));
+    assert!(output.contains(r#"test_latency_sum{method="GET"} 5050"#));
+    assert!(output.contains(r#"test_latency_count{method="GET"} 100"#));
+    assert!(output.contains(r#"test_default_latency{quantile="0.9"} 0.01"#));
+}
+
+#[test]
+fn sampled_histogram_works() {
+    #[prometric_derive::metrics(scope = "test")]
+    struct SampledMetrics {
+        /// Test sampled histogram metric.
+        #[metric(labels = ["method"], sample = 0.5)]
+        sampled: Histogram,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = SampledMetrics::builder().with_registry(&registry).build();
+
+    for _ in 0..1000 {
+        metrics.sampled("GET").observe(1);
+    }
+
+    let families = registry.gather();
+    let histogram = families.iter().find(|f| f.name() == "test_sampled").unwrap();
+    let count = histogram.get_metric()[0].get_histogram().sample_count();
+    assert!(count > 0 && count < 1000, "recorded {count} observations");
+
+    let total = families.iter().find(|f| f.name() == "test_sampled_observations_total").unwrap();
+    assert_eq!(total.get_metric()[0].get_counter().value(), 1000.0);
+}
+
+#[tokio::test]
+async fn timers_work() {
+    #[prometric_derive::metrics(scope = "test")]
+    struct TimerMetrics {
+        /// Test histogram metric for timers.
+        #[metric(labels = ["method"])]
+        duration: Histogram,
+
+        /// Test summary metric for timers.
+        #[metric]
+        summary_duration: Summary,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = TimerMetrics::builder().with_registry(&registry).build();
+
+    // Recorded on drop
+    {
+        let _timer = metrics.duration("guard").start_timer();
+    }
+
+    // Not recorded
+    metrics.duration("discard").start_timer().stop_and_discard();
+
+    let value = metrics.duration("closure").time(|| 42);
+    assert_eq!(value, 42);
+
+    let future = metrics.duration("future").time_future(async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        42
+    });
+    assert_eq!(future.await, 42);
+
+    // Not recorded, since the future never completes
+    drop(metrics.duration("cancelled").time_future(std::future::pending::<()>()));
+
+    metrics.summary_duration().time(|| ());
+
+    let families = registry.gather();
+    let histogram = families.iter().find(|f| f.name() == "test_duration").unwrap();
+    let count = |method: &str| {
+        histogram
+            .get_metric()
+            .iter()
+            .find(|m| m.get_label().iter().any(|l| l.value() == method))
+            .map(|m| m.get_histogram().sample_count())
+    };
+
+    assert_eq!(count("guard"), Some(1));
+    assert_eq!(count("closure"), Some(1));
+    assert_eq!(count("future"), Some(1));
+    assert_eq!(count("discard").unwrap_or_default(), 0);
+    assert_eq!(count("cancelled").unwrap_or_default(), 0);
+
+    let future_sum = histogram
+        .get_metric()
+        .iter()
+        .find(|m| m.get_label().iter().any(|l| l.value() == "future"))
+        .unwrap()
+        .get_histogram()
+        .sample_sum();
+    assert!(future_sum >= 0.01);
+
+    let summary = families.iter().find(|f| f.name() == "test_summary_duration").unwrap();
+    assert_eq!(summary.get_metric()[0].get_summary().sample_count(), 1);
+}
+
+#[tokio::test]
+async fn inflight_guards_work() {
+    #[prometric_derive::metrics(scope = "test")]
+    struct InflightMetrics {
+        /// Test gauge metric for in-flight tracking.
+        #[metric(labels = ["method"])]
+        inflight: Gauge<i64>,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = InflightMetrics::builder().with_registry(&registry).build();
+
+    let value = |registry: &prometheus::Registry| {
+        let families = registry.gather();
+        let gauge = families.iter().find(|f| f.name() == "test_inflight").unwrap();
+        gauge.get_metric()[0].get_gauge().value()
+    };
+
+    let guard = metrics.inflight("GET").track_inflight();
+    let guard2 = metrics.inflight("GET").track_inflight();
+    assert_eq!(value(&registry), 2.0);
+
+    drop(guard);
+    drop(guard2);
+    assert_eq!(value(&registry), 0.0);
+
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let future = metrics.inflight("GET").track_future(async move { rx.await.ok() });
+    assert_eq!(value(&registry), 1.0);
+
+    tx.send(()).unwrap();
+    future.await;
+    assert_eq!(value(&registry), 0.0);
+
+    // Dropping (cancelling) the future decrements the gauge as well.
+    let future = metrics.inflight("GET").track_future(std::future::pending::<()>());
+    assert_eq!(value(&registry), 1.0);
+    drop(future);
+    assert_eq!(value(&registry), 0.0);
+}
+
+#[test]
+fn handles_work() {
+    #[prometric_derive::metrics(scope = "test")]
+    struct HandleMetrics {
+        /// Test counter metric for handles.
+        #[metric(labels = ["method"])]
+        requests: Counter,
+
+        /// Test gauge metric for handles.
+        #[metric(labels = ["method"])]
+        active: Gauge<f64>,
+
+        /// Test histogram metric for handles.
+        #[metric(labels = ["method"])]
+        duration: Histogram,
+
+        /// Test summary metric for handles.
+        #[metric(labels = ["method"])]
+        latency: Summary,
+    }
+
+    fn assert_send_sync<T: Clone + Send + Sync + 'static>(_: &T) {}
+
+    let registry = prometheus::Registry::new();
+    let metrics = HandleMetrics::builder().with_registry(&registry).build();
+
+    let requests = metrics.requests("GET").bind();
+    let active = metrics.active("GET").bind();
+    let duration = metrics.duration("GET").bind();
+    let latency = metrics.latency("GET").bind();
+
+    assert_send_sync(&requests);
+    assert_send_sync(&active);
+    assert_send_sync(&duration);
+    assert_send_sync(&latency);
+
+    let handles = (0..4)
+        .map(|_| {
+            let requests = requests.clone();
+            std::thread::spawn(move || (0..1000).for_each(|_| requests.inc()))
+        })
+        .collect::<Vec<_>>();
+    handles.into_iter().for_each(|h| h.join().unwrap());
+
+    requests.inc_by(5u32);
+    active.set(2.5);
+    active.add(1);
+    duration.observe(1);
+    latency.observe(2.0);
+
+    // Handles and accessors update the same series.
+    metrics.requests("GET").inc();
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"test_requests{method="GET"} 4006"#));
+    assert!(output.contains(r#"test_active{method="GET"} 3.5"#));
+    assert!(output.contains(r#"test_duration_count{method="GET"} 1"#));
+    assert!(output.contains(r#"test_latency_sum{method="GET"} 2"#));
+}
+
+#[test]
+fn typed_labels_work() {
+    #[derive(Clone, Copy, prometric_derive::LabelValue)]
+    enum HttpMethod {
+        Get,
+        PostForm,
+        #[label_value(rename = "DELETE")]
+        Delete,
+    }
+
+    #[derive(prometric_derive::LabelValue)]
+    struct Region(&'static str);
+
+    impl std::fmt::Display for Region {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "region-{}", self.0)
+        }
+    }
+
+    #[prometric_derive::metrics(scope = "test")]
+    struct TypedMetrics {
+        /// Test counter metric with typed labels.
+        #[metric(labels = [method: HttpMethod, status: u16, "path"])]
+        requests: Counter,
+
+        /// Test gauge metric with untyped labels.
+        #[metric(labels = ["region", "healthy"])]
+        nodes: Gauge,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = TypedMetrics::builder().with_registry(&registry).build();
+
+    let path = String::from("/");
+    metrics.requests(HttpMethod::Get, 200, "/").inc();
+    metrics.requests(HttpMethod::PostForm, 201, &path).inc();
+    metrics.requests(HttpMethod::Delete, 404, path).inc();
+
+    // Untyped labels accept any label value.
+    let region = Region("us");
+    metrics.nodes(Region("eu"), true).set(3);
+    metrics.nodes(&region, false).set(1);
+    assert_eq!(region.to_string(), "region-us");
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"test_requests{method="get",path="/",status="200"} 1"#));
+    assert!(output.contains(r#"test_requests{method="post_form",path="/",status="201"} 1"#));
+    assert!(output.contains(r#"test_requests{method="DELETE",path="/",status="404"} 1"#));
+    assert!(output.contains(r#"test_nodes{healthy="true",region="region-eu"} 3"#));
+    assert!(output.contains(r#"test_nodes{healthy="false",region="region-us"} 1"#));
+}
+
+#[test]
+fn label_sets_work() {
+    #[derive(Clone, Copy, prometric_derive::LabelValue)]
+    enum Method {
+        Get,
+        Post,
+    }
+
+    #[derive(prometric_derive::LabelSet)]
+    struct RequestLabels {
+        method: Method,
+        path: String,
+        status: u16,
+        r#type: &'static str,
+    }
+
+    #[prometric_derive::metrics(scope = "test")]
+    struct LabelSetMetrics {
+        /// Test counter metric with a label set.
+        #[metric(labels = RequestLabels)]
+        requests: Counter,
+
+        /// Test histogram metric sharing the label set.
+        #[metric(labels = RequestLabels)]
+        duration: Histogram,
+    }
+
+    assert_eq!(
+        <RequestLabels as prometric::LabelSet>::LABEL_NAMES,
+        &["method", "path", "status", "type"]
+    );
+
+    let registry = prometheus::Registry::new();
+    let metrics = LabelSetMetrics::builder().with_registry(&registry).build();
+
+    let get = RequestLabels { method: Method::Get, path: "/".into(), status: 200, r#type: "api" };
+    let post = RequestLabels { method: Method::Post, path: "/".into(), status: 201, r#type: "api" };
+
+    metrics.requests(&get).inc();
+    metrics.requests(&get).bind().inc();
+    metrics.requests(&post).inc_by(3u32);
+    metrics.duration(&get).observe(0.5);
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"test_requests{method="get",path="/",status="200",type="api"} 2"#));
+    assert!(output.contains(r#"test_requests{method="post",path="/",status="201",type="api"} 3"#));
+    assert!(
+        output.contains(r#"test_duration_count{method="get",path="/",status="200",type="api"} 1"#)
+    );
+}
+
+#[test]
+fn nested_metrics_work() {
+    #[prometric_derive::metrics(scope = "rpc")]
+    struct RpcMetrics {
+        /// Test counter metric in a nested struct.
+        #[metric(labels = ["method"])]
+        calls: Counter,
+    }
+
+    #[prometric_derive::metrics(scope = "server")]
+    struct ServerMetrics {
+        /// Test gauge metric in the parent struct.
+        #[metric]
+        connections: Gauge,
+
+        #[metric(flatten)]
+        flat: RpcMetrics,
+
+        #[metric(scope = "public")]
+        public: RpcMetrics,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics =
+        ServerMetrics::builder().with_registry(&registry).with_label("host", "localhost").build();
+
+    metrics.connections().set(2);
+    metrics.flat().calls("eth_call").inc();
+    metrics.public().calls("eth_call").inc_by(2u32);
+
+    // The nested builder keeps its own scope when used on its own.
+    let rpc = RpcMetrics::builder().with_registry(&registry).build();
+    rpc.calls("eth_call").inc_by(3u32);
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"server_connections{host="localhost"} 2"#));
+    assert!(output.contains(r#"server_calls{host="localhost",method="eth_call"} 1"#));
+    assert!(output.contains(r#"server_public_calls{host="localhost",method="eth_call"} 2"#));
+    assert!(output.contains(r#"rpc_calls{method="eth_call"} 3"#));
+}
+
+#[test]
+fn skipped_fields_work() {
+    #[derive(Debug, Default, PartialEq)]
+    struct Config {
+        name: String,
+    }
+
+    #[prometric_derive::metrics(scope = "test")]
+    struct SkipMetrics {
+        /// Test counter metric next to skipped fields.
+        #[metric]
+        requests: Counter,
+
+        #[metric(skip)]
+        config: Config,
+
+        #[metric(skip, default = std::time::Instant::now())]
+        started: std::time::Instant,
+    }
+
+    let registry = prometheus::Registry::new();
+    let before = std::time::Instant::now();
+    let metrics = SkipMetrics::builder()
+        .with_registry(&registry)
+        .with_config(Config { name: "test".to_owned() })
+        .build();
+
+    assert_eq!(metrics.config, Config { name: "test".to_owned() });
+    assert!(metrics.started >= before);
+
+    // Skipped fields fall back to their default when not set.
+    let metrics = SkipMetrics::builder().with_registry(&registry).build();
+    assert_eq!(metrics.config, Config::default());
+    metrics.requests().inc();
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains("test_requests 1"));
+}
+
+#[test]
+fn default_scope_works() {
+    #[prometric_derive::metrics]
+    struct HttpServerMetrics {
+        /// Test counter metric without an explicit scope.
+        #[metric]
+        requests: Counter,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = HttpServerMetrics::builder().with_registry(&registry).build();
+    metrics.requests().inc();
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains("http_server_requests 1"));
+}
+
+#[test]
+#[should_panic(expected = "Conflicting definitions for metric `shared_requests`")]
+fn conflicting_definitions_panic() {
+    #[prometric_derive::metrics(scope = "shared")]
+    struct FirstMetrics {
+        /// Test counter metric.
+        #[metric(labels = ["method"])]
+        requests: Counter,
+    }
+
+    #[prometric_derive::metrics(scope = "shared")]
+    struct SecondMetrics {
+        /// Test gauge metric with the same name.
+        #[metric(rename = "requests", labels = ["method"])]
+        active_requests: Gauge,
+    }
+
+    let registry = prometheus::Registry::new();
+    let first = FirstMetrics::builder().with_registry(&registry).build();
+    first.requests("GET").inc();
+
+    let second = SecondMetrics::builder().with_registry(&registry).build();
+    second.active_requests("GET").inc();
+}
+
+#[test]
+fn conflict_policies_work() {
+    #[prometric_derive::metrics(scope = "policy")]
+    struct PolicyMetrics {
+        /// Test counter metric for conflict policies.
+        #[metric(labels = ["method"])]
+        requests: Counter,
+
+        /// Test sampled histogram metric for conflict policies.
+        #[metric(sample = 1.0)]
+        duration: Histogram,
+    }
+
+    let registry = prometheus::Registry::new();
+    let first = PolicyMetrics::builder().with_registry(&registry).try_build().unwrap();
+    first.requests("GET").inc();
+
+    let err = PolicyMetrics::builder()
+        .with_registry(&registry)
+        .on_conflict(prometric::ConflictPolicy::Error)
+        .try_build()
+        .err()
+        .unwrap();
+    assert!(matches!(err, prometric::Error::AlreadyRegistered(_)), "{err}");
+
+    // Reused metrics share their series.
+    let second = PolicyMetrics::builder()
+        .with_registry(&registry)
+        .on_conflict(prometric::ConflictPolicy::Reuse)
+        .try_build()
+        .unwrap();
+    second.requests("GET").inc();
+    second.duration().observe(1.0);
+    first.duration().observe(2.0);
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"policy_requests{method="GET"} 2"#));
+    assert!(output.contains("policy_duration_count 2"));
+    assert!(output.contains("policy_duration_observations_total 2"));
+
+    // Replaced metrics start from scratch.
+    let third = PolicyMetrics::builder()
+        .with_registry(&registry)
+        .on_conflict(prometric::ConflictPolicy::Replace)
+        .try_build()
+        .unwrap();
+    third.requests("POST").inc();
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(!output.contains(r#"policy_requests{method="GET"}"#));
+    assert!(output.contains(r#"policy_requests{method="POST"} 1"#));
+}
+
+#[test]
+fn instances_with_static_labels_work() {
+    #[prometric_derive::metrics(scope = "shard")]
+    struct ShardMetrics {
+        /// Test counter metric for shards.
+        #[metric(labels = ["kind"])]
+        messages: Counter,
+
+        /// Test sampled histogram metric for shards.
+        #[metric(sample = 1.0)]
+        latency: Histogram,
+
+        /// Test summary metric for shards.
+        #[metric]
+        size: Summary,
+    }
+
+    let registry = prometheus::Registry::new();
+    let shards = (0..2)
+        .map(|i| {
+            ShardMetrics::builder()
+                .with_registry(&registry)
+                .with_label("shard", i.to_string())
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    shards[0].messages("block").inc();
+    shards[1].messages("block").inc_by(2u64);
+    shards[1].latency().observe(1.0);
+    shards[0].size().observe(3.0);
+
+    // A reused instance of the same shard shares its series.
+    let shard = ShardMetrics::builder()
+        .with_registry(&registry)
+        .with_label("shard", "1")
+        .on_conflict(prometric::ConflictPolicy::Reuse)
+        .build();
+    shard.messages("block").inc();
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"shard_messages{kind="block",shard="0"} 1"#), "{output}");
+    assert!(output.contains(r#"shard_messages{kind="block",shard="1"} 3"#));
+    assert!(output.contains(r#"shard_latency_count{shard="1"} 1"#));
+    assert!(output.contains(r#"shard_latency_observations_total{shard="1"} 1"#));
+    assert!(output.contains(r#"shard_size_count{shard="0"} 1"#));
+
+    // Replacing an instance only replaces the series of its shard.
+    let shard = ShardMetrics::builder().with_registry(&registry).with_label("shard", "1").build();
+    shard.messages("block").inc();
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"shard_messages{kind="block",shard="0"} 1"#), "{output}");
+    assert!(output.contains(r#"shard_messages{kind="block",shard="1"} 1"#));
+    assert!(!output.contains(r#"shard_latency_count{shard="1"}"#));
+    assert!(output.contains(r#"shard_size_count{shard="0"} 1"#));
+
+    // Instances with different label names conflict.
+    let err = ShardMetrics::builder().with_registry(&registry).try_build().err().unwrap();
+    assert!(matches!(err, prometric::Error::Conflict(_)), "{err}");
+}
+
+#[test]
+fn units_work() {
+    #[prometric_derive::metrics(scope = "unit")]
+    struct UnitMetrics {
+        /// Test histogram metric in seconds.
+        #[metric(unit = "seconds")]
+        latency: Histogram,
+
+        /// Test summary metric in seconds, which already has the suffix.
+        #[metric(rename = "size_seconds", unit = "seconds")]
+        size: Summary,
+
+        /// Test counter metric in bytes.
+        #[metric(rename = "received_total", unit = "bytes")]
+        received: Counter,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = UnitMetrics::builder().with_registry(&registry).build();
+
+    metrics.latency().observe(std::time::Duration::from_millis(500));
+    metrics.latency().observe(1.5);
+    metrics.size().observe(std::time::Duration::from_secs(2));
+    metrics.received().inc_by(10u64);
+    // Handles accept a `Duration` as well.
+    metrics.latency().bind().observe(std::time::Duration::from_millis(250));
+    metrics.size().bind().observe(std::time::Duration::from_secs(1));
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains("unit_latency_seconds_sum 2.25"), "{output}");
+    assert!(output.contains("unit_size_seconds_sum 3"));
+    assert!(output.contains("unit_received_bytes_total 10"));
+}
+
+#[test]
+fn counter_suffix_works() {
+    #[prometric_derive::metrics(scope = "suffix", counter_suffix = "auto")]
+    struct SuffixMetrics {
+        /// Test counter metric without the suffix.
+        #[metric]
+        errors: Counter,
+
+        /// Test counter metric with the suffix.
+        #[metric(rename = "retries_total")]
+        retries: Counter,
+
+        /// Test counter metric with a unit.
+        #[metric(unit = "bytes")]
+        sent: Counter,
+
+        /// Test gauge metric, which doesn't get the suffix.
+        #[metric]
+        queued: Gauge,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = SuffixMetrics::builder().with_registry(&registry).build();
+    metrics.errors().inc();
+    metrics.retries().inc();
+    metrics.sent().inc();
+    metrics.queued().inc();
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains("suffix_errors_total 1"), "{output}");
+    assert!(output.contains("suffix_retries_total 1"));
+    assert!(output.contains("suffix_sent_bytes_total 1"));
+    assert!(output.contains("suffix_queued 1"));
+}
+
+#[test]
+fn bucket_helpers_work() {
+    const BUCKETS: [f64; 3] = prometric::buckets::linear(1.0, 1.0);
+
+    #[prometric_derive::metrics(scope = "buckets")]
+    struct BucketMetrics {
+        /// Test histogram metric with exponential buckets.
+        #[metric(buckets = exponential(0.001, 10.0, 4))]
+        exponential: Histogram,
+
+        /// Test histogram metric with an exponential range of buckets.
+        #[metric(buckets = exponential_range(1.0, 100.0, 3))]
+        range: Histogram,
+
+        /// Test histogram metric with the default duration buckets.
+        #[metric(buckets = duration_seconds_default())]
+        duration: Histogram,
+
+        /// Test histogram metric with a constant.
+        #[metric(buckets = BUCKETS)]
+        constant: Histogram,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = BucketMetrics::builder().with_registry(&registry).build();
+    metrics.exponential().observe(0.5);
+    metrics.range().observe(0.5);
+    metrics.duration().observe(0.5);
+    metrics.constant().observe(0.5);
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"buckets_exponential_bucket{le="0.001"} 0"#), "{output}");
+    assert!(output.contains(r#"buckets_exponential_bucket{le="1"} 1"#));
+    assert!(output.contains(r#"buckets_range_bucket{le="10"} 1"#));
+    assert!(output.contains(r#"buckets_duration_bucket{le="30"} 1"#));
+    assert!(output.contains(r#"buckets_constant_bucket{le="3"} 1"#));
+}
+
+#[test]
+fn bucket_overrides_work() {
+    #[prometric_derive::metrics(scope = "overrides")]
+    struct OverrideMetrics {
+        /// Test histogram metric with buckets.
+        #[metric(buckets = [1.0, 2.0])]
+        latency: Histogram,
+
+        /// Test histogram metric with the default buckets.
+        #[metric]
+        size: Histogram,
+
+        /// Test counter metric.
+        #[metric]
+        errors: Counter,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = OverrideMetrics::builder()
+        .with_registry(&registry)
+        .with_buckets("latency", [10.0, 20.0])
+        .with_buckets("size", vec![100.0])
+        .build();
+    metrics.latency().observe(15.0);
+    metrics.size().observe(50.0);
+    metrics.errors().inc();
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"overrides_latency_bucket{le="10"} 0"#), "{output}");
+    assert!(output.contains(r#"overrides_latency_bucket{le="20"} 1"#));
+    assert!(!output.contains(r#"overrides_latency_bucket{le="1"}"#));
+    assert!(output.contains(r#"overrides_size_bucket{le="100"} 1"#));
+
+    let registry = prometheus::Registry::new();
+    let builder = || OverrideMetrics::builder().with_registry(&registry);
+
+    let err = builder().with_buckets("errors", [1.0]).try_build().err().unwrap();
+    assert!(err.to_string().contains("is not a histogram"), "{err}");
+    let err = builder().with_buckets("unknown", [1.0]).try_build().err().unwrap();
+    assert!(err.to_string().contains("does not exist"), "{err}");
+
+    // Registering the histogram again with different buckets conflicts, whatever the policy.
+    let _metrics = builder().build();
+    let err = builder()
+        .with_buckets("latency", [10.0, 20.0, 30.0])
+        .on_conflict(prometric::ConflictPolicy::Reuse)
+        .try_build()
+        .err()
+        .unwrap();
+    assert!(matches!(err, prometric::Error::Conflict(_)), "{err}");
+    assert!(err.to_string().contains("buckets [10.0, 20.0, 30.0]"), "{err}");
+    let _metrics = builder().on_conflict(prometric::ConflictPolicy::Reuse).try_build().unwrap();
+}
+
+#[test]
+fn cardinality_limits_work() {
+    #[prometric_derive::metrics(scope = "limited")]
+    struct LimitedMetrics {
+        /// Test counter metric with a cardinality limit.
+        #[metric(labels = ["method", "path"], max_cardinality = 2)]
+        requests: Counter,
+
+        /// Test histogram metric with a cardinality limit and a custom overflow value.
+        #[metric(labels = ["path"], max_cardinality = 1, overflow = "other")]
+        latency: Histogram,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics =
+        LimitedMetrics::builder().with_registry(&registry).with_label("shard", "0").build();
+    metrics.requests("GET", "/a").inc();
+    metrics.requests("GET", "/b").inc();
+    metrics.requests("GET", "/c").inc();
+    metrics.requests("POST", "/d").inc();
+    metrics.requests("GET", "/a").inc();
+    metrics.latency("/a").observe(1.0);
+    metrics.latency("/b").observe(1.0);
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"limited_requests{method="GET",path="/a",shard="0"} 2"#), "{output}");
+    assert!(output.contains(r#"limited_requests{method="GET",path="/b",shard="0"} 1"#));
+    assert!(
+        output
+            .contains(r#"limited_requests{method="__overflow__",path="__overflow__",shard="0"} 2"#)
+    );
+    assert!(!output.contains(r#"path="/c""#));
+    assert!(output.contains(r#"limited_latency_count{path="other",shard="0"} 1"#));
+    assert!(
+        output.contains(r#"prometric_cardinality_limit_hits_total{metric="limited_requests"} 2"#)
+    );
+    assert!(
+        output.contains(r#"prometric_cardinality_limit_hits_total{metric="limited_latency"} 1"#)
+    );
+}
+
+#[test]
+fn ttl_works() {
+    #[prometric_derive::metrics(scope = "expiring")]
+    struct ExpiringMetrics {
+        /// Test gauge metric with a TTL.
+        #[metric(labels = ["peer"], ttl = "50ms")]
+        peer_score: Gauge,
+
+        /// Test histogram metric with a TTL and sampling.
+        #[metric(labels = ["peer"], ttl = "50ms", sample = 1.0)]
+        peer_latency: Histogram,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = ExpiringMetrics::builder().with_registry(&registry).build();
+    metrics.peer_score("a").set(1);
+    metrics.peer_score("b").set(2);
+    metrics.peer_latency("a").observe(1.0);
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"expiring_peer_score{peer="a"} 1"#), "{output}");
+    assert!(output.contains(r#"expiring_peer_latency_count{peer="a"} 1"#));
+
+    std::thread::sleep(Duration::from_millis(100));
+    metrics.peer_score("b").set(3);
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(!output.contains(r#"expiring_peer_score{peer="a"}"#), "{output}");
+    assert!(output.contains(r#"expiring_peer_score{peer="b"} 3"#));
+    assert!(!output.contains(r#"expiring_peer_latency_count{peer="a"}"#));
+    assert!(!output.contains(r#"expiring_peer_latency_observations_total{peer="a"}"#));
+
+    // Expired series are created again on their next update.
+    metrics.peer_score("a").set(4);
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"expiring_peer_score{peer="a"} 4"#));
+}
+
+#[test]
+fn removing_series_works() {
+    #[prometric_derive::metrics(scope = "peers")]
+    struct PeerStats {
+        /// Test counter metric.
+        #[metric(labels = ["peer"])]
+        messages: Counter,
+    }
+
+    #[prometric_derive::metrics(scope = "tenants")]
+    struct TenantMetrics {
+        /// Test gauge metric.
+        #[metric(labels = ["peer"])]
+        connected: Gauge,
+
+        /// Test summary metric.
+        #[metric(labels = ["peer"])]
+        rtt: Summary,
+
+        /// Test nested metrics.
+        #[metric(flatten)]
+        stats: PeerStats,
+    }
+
+    let registry = prometheus::Registry::new();
+    let tenant = |name: &str| {
+        TenantMetrics::builder().with_registry(&registry).with_label("tenant", name).build()
+    };
+    let (first, second) = (tenant("a"), tenant("b"));
+    for metrics in [&first, &second] {
+        for peer in ["1", "2"] {
+            metrics.connected(peer).set(1);
+            metrics.rtt(peer).observe(0.1);
+            metrics.stats().messages(peer).inc();
+        }
+    }
+
+    let output = || prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+
+    first.connected("1").remove();
+    first.rtt("1").remove();
+    let text = output();
+    assert!(!text.contains(r#"tenants_connected{peer="1",tenant="a"}"#), "{text}");
+    assert!(!text.contains(r#"tenants_rtt_count{peer="1",tenant="a"}"#));
+    assert!(text.contains(r#"tenants_connected{peer="2",tenant="a"} 1"#));
+    assert!(text.contains(r#"tenants_connected{peer="1",tenant="b"} 1"#));
+
+    second.clear_connected();
+    let text = output();
+    assert!(!text.contains(r#"tenants_connected{peer="1",tenant="b"}"#), "{text}");
+    assert!(!text.contains(r#"tenants_connected{peer="2",tenant="b"}"#));
+    assert!(text.contains(r#"tenants_connected{peer="2",tenant="a"} 1"#));
+
+    // Resetting removes the series of all metrics of the instance, including nested metrics.
+    first.reset_all();
+    let text = output();
+    assert!(!text.contains(r#"tenant="a""#), "{text}");
+    assert!(text.contains(r#"tenants_messages{peer="1",tenant="b"} 1"#));
+    assert!(text.contains(r#"tenants_rtt_count{peer="2",tenant="b"} 1"#));
+
+    // Removed series are created again on their next update.
+    first.stats().messages("1").inc();
+    assert!(output().contains(r#"tenants_messages{peer="1",tenant="a"} 1"#));
+}
+
+#[test]
+fn reading_values_works() {
+    #[prometric_derive::metrics(scope = "readback")]
+    struct ReadbackMetrics {
+        /// Test counter metric.
+        #[metric(labels = ["method"])]
+        requests: Counter,
+
+        /// Test gauge metric.
+        #[metric]
+        queue_depth: Gauge<f64>,
+
+        /// Test histogram metric.
+        #[metric(labels = ["method"], buckets = [1.0, 2.0])]
+        latency: Histogram,
+
+        /// Test counter metric with a cardinality limit.
+        #[metric(labels = ["peer"], max_cardinality = 1)]
+        limited: Counter,
+    }
+
+    let registry = prometheus::Registry::new();
+    let metrics = ReadbackMetrics::builder().with_registry(&registry).build();
+    metrics.requests("GET").inc_by(3u64);
+    metrics.queue_depth().set(1.5);
+    metrics.latency("GET").observe(0.5);
+    metrics.latency("GET").observe(1.5);
+    metrics.latency("GET").observe(4.0);
+
+    assert_eq!(metrics.requests("GET").get(), 3);
+    assert_eq!(metrics.requests("POST").get(), 0);
+    assert_eq!(metrics.queue_depth().get(), 1.5);
+    assert_eq!(metrics.queue_depth().bind().get(), 1.5);
+
+    let snapshot = metrics.latency("GET").snapshot();
+    assert_eq!(snapshot.count, 3);
+    assert_eq!(snapshot.sum, 6.0);
+    assert_eq!(snapshot.buckets, [(1.0, 1), (2.0, 2)]);
+    assert_eq!(metrics.latency("GET").bind().snapshot(), snapshot);
+
+    // Reading doesn't create series or count against the cardinality limit.
+    assert_eq!(metrics.limited("a").get(), 0);
+    assert_eq!(metrics.latency("POST").snapshot().buckets, [(1.0, 0), (2.0, 0)]);
+    metrics.limited("b").inc();
+
+    let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+    assert!(output.contains(r#"readback_limited{peer="b"} 1"#), "{output}");
+    assert!(!output.contains(r#"peer="a""#));
+    assert!(!output.contains(r#"__overflow__"#));
+    assert!(!output.contains(r#"readback_requests{method="POST"}"#));
+    assert!(!output.contains(r#"readback_latency_count{method="POST"}"#));
+}
+
+#[test]
+fn testing_helpers_work() {
+    #[prometric_derive::metrics(scope = "tested")]
+    struct TestedMetrics {
+        /// Test counter metric.
+        #[metric(labels = ["kind"])]
+        errors: Counter,
+
+        /// Test gauge metric.
+        #[metric]
+        connections: Gauge,
+    }
+
+    let registry = prometric::testing::TestRegistry::new();
+    let metrics = TestedMetrics::builder().with_registry(&registry).with_label("host", "x").build();
+    metrics.errors("timeout").inc_by(3u64);
+    metrics.connections().set(2);
+
+    prometric::assert_counter!(registry, "tested_errors", { "host" => "x", "kind" => "timeout" }, 3);
+    prometric::assert_gauge!(registry, "tested_connections", 2);
+    prometric::assert_exposition!(
+        registry,
+        r#"
+        # HELP tested_connections Test gauge metric.
+        # TYPE tested_connections gauge
+        tested_connections{host="x"} <value>
+        # HELP tested_errors Test counter metric.
+        # TYPE tested_errors counter
+        tested_errors{host="x",kind="timeout"} <value>
+        "#
+    );
+}
+
+#[test]
+fn descriptors_work() {
+    use prometheus::proto::MetricType;
+    use prometric::{MetricDescriptor, MetricsGroup};
+
+    #[prometric_derive::metrics(scope = "rpc")]
+    struct DescribedRpcMetrics {
+        /// Test counter metric in a nested struct.
+        #[metric(labels = ["method"])]
+        calls: Counter,
+    }
+
+    #[prometric_derive::metrics(scope = "described")]
+    struct DescribedMetrics {
+        /// Test counter metric.
+        #[metric(labels = ["method", "path"])]
+        requests: Counter,
+
+        /// Test gauge metric.
+        #[metric(unit = "bytes")]
+        size: Gauge,
+
+        /// Test histogram metric.
+        #[metric(buckets = [1.0, 2.0])]
+        latency: Histogram,
+
+        /// Test histogram metric with a bucket helper.
+        #[metric(buckets = exponential(1.0, 10.0, 3))]
+        exponential: Histogram,
+
+        /// Test histogram metric with the default buckets.
+        #[metric]
+        duration: Histogram,
+
+        #[metric(scope = "public")]
+        public: DescribedRpcMetrics,
+    }
+
+    assert_eq!(DescribedMetrics::SCOPE, "described");
+    assert_eq!(DescribedMetrics::DESCRIPTORS.len(), 5);
+    assert_eq!(
+        DescribedMetrics::DESCRIPTORS[0],
+        MetricDescriptor {
+            field: "requests",
+            name: "described_requests".into(),
+            kind: MetricType::COUNTER,
+            help: "Test counter metric.",
+            labels: &["method", "path"],
+            unit: None,
+            buckets: None,
+        }
+    );
+
+    let descriptors = DescribedMetrics::descriptors();
+    let names = descriptors.iter().map(|d| d.name.as_ref()).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "described_requests",
+            "described_size_bytes",
+            "described_latency",
+            "described_exponential",
+            "described_duration",
+            "described_public_calls",
+        ]
+    );
+    assert_eq!(descriptors[1].unit, Some("bytes"));
+    assert_eq!(descriptors[2].buckets, Some(&[1.0, 2.0][..]));
+    assert_eq!(descriptors[3].buckets, Some(&[1.0, 10.0, 100.0][..]));
+    assert_eq!(descriptors[4].buckets, Some(prometheus::DEFAULT_BUCKETS as &[f64]));
+    assert_eq!(descriptors[5].labels, ["method"]);
+
+    // The names match the registered metrics.
+    let registry = prometheus::Registry::new();
+    let metrics = DescribedMetrics::builder().with_registry(&registry).build();
+    metrics.requests("GET", "/").inc();
+    metrics.size().set(1);
+    metrics.latency().observe(1.0);
+    metrics.exponential().observe(1.0);
+    metrics.duration().observe(1.0);
+    metrics.public().calls("eth_call").inc();
+    let mut families = registry.gather().iter().map(|f| f.name().to_owned()).collect::<Vec<_>>();
+    families.sort();
+    let mut expected = names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(families, expected);
+
+    // Nested metrics are described with the scope of the parent.
+    let nested = DescribedRpcMetrics::descriptors_in("other");
+    assert_eq!(nested[0].name, "other_calls");
+    assert_eq!(DescribedRpcMetrics::descriptors()[0].name, "rpc_calls");
+}
@@ -0,0 +1,8 @@
+//! Compile-fail tests for the errors of the macros. Update the expected output with
+//! `TRYBUILD=overwrite cargo test --test ui`.
+
+#[test]
+fn ui() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,8 @@
+#[prometric_derive::metrics(scope = "app", counter_suffix = "require")]
+struct Metrics {
+    /// The total number of requests.
+    #[metric(labels = ["method"])]
+    requests: prometric::Counter,
+}
+
+fn main() {}
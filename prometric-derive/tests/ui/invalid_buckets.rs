@@ -0,0 +1,29 @@
+#[prometric_derive::metrics(scope = "app")]
+struct Decreasing {
+    /// The duration of requests.
+    #[metric(buckets = [0.1, 0.5, 0.5, 1.0])]
+    duration: prometric::Histogram,
+}
+
+#[prometric_derive::metrics(scope = "app")]
+struct Infinite {
+    /// The duration of requests.
+    #[metric(buckets = [0.1, 1.0, f64::INFINITY])]
+    duration: prometric::Histogram,
+}
+
+#[prometric_derive::metrics(scope = "app")]
+struct InfiniteHelper {
+    /// The duration of requests.
+    #[metric(buckets = linear(f64::INFINITY, 1.0, 5))]
+    duration: prometric::Histogram,
+}
+
+#[prometric_derive::metrics(scope = "app")]
+struct InvalidHelper {
+    /// The duration of requests.
+    #[metric(buckets = exponential(0.001, 1.0, 5))]
+    duration: prometric::Histogram,
+}
+
+fn main() {}
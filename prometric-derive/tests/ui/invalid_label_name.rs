@@ -0,0 +1,8 @@
+#[prometric_derive::metrics(scope = "app")]
+struct Metrics {
+    /// The total number of requests.
+    #[metric(labels = ["http-method"])]
+    requests: prometric::Counter,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+#[derive(prometric_derive::LabelSet)]
+struct Invalid {
+    größe: String,
+}
+
+#[derive(prometric_derive::LabelSet)]
+struct Reserved {
+    le: String,
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+#[prometric_derive::metrics(scope = "app")]
+struct Metrics {
+    /// The total number of requests.
+    #[metric(rename = "requests-total")]
+    requests: prometric::Counter,
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+#[prometric_derive::metrics(scope = "my-app")]
+struct Metrics {
+    /// The total number of requests.
+    #[metric]
+    requests: prometric::Counter,
+}
+
+fn main() {}
@@ -0,0 +1,23 @@
+#[prometric_derive::metrics(scope = "app")]
+struct Metrics {
+    /// The duration of requests.
+    #[metric]
+    duration: prometric::Histogram,
+
+    /// The number of requests.
+    #[metric(rename = "duration_count")]
+    requests: prometric::Gauge,
+}
+
+#[prometric_derive::metrics(scope = "app")]
+struct Renamed {
+    /// The number of requests.
+    #[metric]
+    requests: prometric::Gauge,
+
+    /// The number of requests, renamed.
+    #[metric(rename = "requests")]
+    other: prometric::Gauge,
+}
+
+fn main() {}
@@ -0,0 +1,22 @@
+#[prometric_derive::metrics(scope = "app")]
+struct Le {
+    /// The duration of requests.
+    #[metric(labels = ["le"])]
+    duration: prometric::Histogram,
+}
+
+#[prometric_derive::metrics(scope = "app")]
+struct Quantile {
+    /// The size of requests.
+    #[metric(labels = ["quantile"])]
+    size: prometric::Summary,
+}
+
+#[prometric_derive::metrics(scope = "app")]
+struct Internal {
+    /// The total number of requests.
+    #[metric(labels = ["__name"])]
+    requests: prometric::Counter,
+}
+
+fn main() {}
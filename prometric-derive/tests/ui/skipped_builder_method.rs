@@ -0,0 +1,11 @@
+#[prometric_derive::metrics(scope = "app")]
+struct Metrics {
+    /// The total number of requests.
+    #[metric]
+    requests: prometric::Counter,
+
+    #[metric(skip)]
+    label: String,
+}
+
+fn main() {}
@@ -0,0 +1,177 @@
+//! Helpers to generate histogram buckets.
+//!
+//! All helpers are `const fn`s that return an array, so they can be used to define constants, and
+//! directly in the `buckets` attribute of the `prometric-derive` crate:
+//!
+//! ```rust
+//! const LATENCY_BUCKETS: [f64; 15] = prometric::buckets::exponential(0.001, 2.0);
+//!
+//! assert_eq!(LATENCY_BUCKETS[0], 0.001);
+//! assert_eq!(LATENCY_BUCKETS[14], 16.384);
+//! ```
+
+/// `N` buckets, where the lowest bucket has an upper bound of `start` and each following bucket's
+/// upper bound is `factor` times the previous one.
+///
+/// # Panics
+/// Panics if `N` is zero, `start` is not positive, `factor` is not greater than 1, either isn't
+/// finite, or the upper bound of the highest bucket overflows to infinity.
+pub const fn exponential<const N: usize>(start: f64, factor: f64) -> [f64; N] {
+    assert!(N > 0, "The number of buckets must be positive");
+    assert!(start > 0.0, "The start of exponential buckets must be positive");
+    assert!(factor > 1.0, "The factor of exponential buckets must be greater than 1");
+    assert!(
+        start.is_finite() && factor.is_finite(),
+        "The start and factor of exponential buckets must be finite"
+    );
+
+    let mut buckets = [0.0; N];
+    let mut bound = start;
+    let mut i = 0;
+    while i < N {
+        assert!(bound.is_finite(), "The upper bounds of exponential buckets must be finite");
+        buckets[i] = round(bound);
+        bound *= factor;
+        i += 1;
+    }
+
+    buckets
+}
+
+/// `N` buckets, where the lowest bucket has an upper bound of `start` and each following bucket's
+/// upper bound is `width` more than the previous one.
+///
+/// # Panics
+/// Panics if `N` is zero, `width` is not positive, `start` or `width` isn't finite, or the upper
+/// bound of the highest bucket overflows to infinity.
+pub const fn linear<const N: usize>(start: f64, width: f64) -> [f64; N] {
+    assert!(N > 0, "The number of buckets must be positive");
+    assert!(width > 0.0, "The width of linear buckets must be positive");
+    assert!(
+        start.is_finite() && width.is_finite(),
+        "The start and width of linear buckets must be finite"
+    );
+
+    let mut buckets = [0.0; N];
+    let mut i = 0;
+    while i < N {
+        let bound = start + width * i as f64;
+        assert!(bound.is_finite(), "The upper bounds of linear buckets must be finite");
+        buckets[i] = round(bound);
+        i += 1;
+    }
+
+    buckets
+}
+
+/// `N` exponential buckets from `min` up to and including `max`.
+///
+/// # Panics
+/// Panics if `N` is less than 2, `min` is not positive, `max` is not greater than `min`, or `max`
+/// isn't finite.
+pub const fn exponential_range<const N: usize>(min: f64, max: f64) -> [f64; N] {
+    assert!(N >= 2, "The number of buckets in a range must be at least 2");
+    assert!(min > 0.0, "The minimum of exponential buckets must be positive");
+    assert!(max > min, "The maximum of exponential buckets must be greater than the minimum");
+    assert!(max.is_finite(), "The maximum of exponential buckets must be finite");
+
+    let mut buckets = exponential(min, nth_root(max / min, N as u32 - 1));
+    // Avoid rounding errors in the last bucket.
+    buckets[N - 1] = max;
+    buckets
+}
+
+/// Buckets for durations in seconds, from 1 millisecond to 30 seconds.
+pub const fn duration_seconds_default() -> [f64; 14] {
+    [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]
+}
+
+/// Round to 12 significant digits, so that e.g. `0.1 * 3.0` becomes `0.3`. Values that can't be
+/// scaled, i.e. non-finite and subnormal values, are returned as is.
+const fn round(value: f64) -> f64 {
+    if value == 0.0 || !value.is_normal() {
+        return value;
+    }
+
+    // Scale the value so that the rounding is relative to its magnitude.
+    let mut scale = 1e12;
+    let mut magnitude = if value < 0.0 { -value } else { value };
+    while magnitude >= 1.0 {
+        magnitude /= 10.0;
+        scale /= 10.0;
+    }
+    while magnitude < 0.1 {
+        magnitude *= 10.0;
+        scale *= 10.0;
+    }
+
+    let scaled = value * scale;
+    // NOTE: The scale overflows for values close to the smallest normal value.
+    if !scaled.is_finite() {
+        return value;
+    }
+    let rounded = if scaled < 0.0 { scaled - 0.5 } else { scaled + 0.5 };
+    (rounded as i64) as f64 / scale
+}
+
+/// The `n`th root of `value`, which must be greater than 1. `f64::powf` isn't a `const fn`, so this
+/// uses Newton's method.
+const fn nth_root(value: f64, n: u32) -> f64 {
+    let mut x = value;
+    let mut i = 0;
+    while i < 100 {
+        let mut pow = 1.0;
+        let mut j = 1;
+        while j < n {
+            pow *= x;
+            j += 1;
+        }
+
+        let next = x - (pow * x - value) / (n as f64 * pow);
+        if next == x {
+            break;
+        }
+        x = next;
+        i += 1;
+    }
+
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets() {
+        assert_eq!(exponential::<4>(1.0, 2.0), [1.0, 2.0, 4.0, 8.0]);
+        assert_eq!(exponential::<3>(0.001, 10.0), [0.001, 0.01, 0.1]);
+        assert_eq!(linear::<4>(0.1, 0.1), [0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(linear::<3>(-1.0, 1.0), [-1.0, 0.0, 1.0]);
+        assert_eq!(exponential_range::<4>(1.0, 1000.0), [1.0, 10.0, 100.0, 1000.0]);
+        assert_eq!(exponential_range::<3>(0.001, 1.0), [0.001, 0.0316227766017, 1.0]);
+
+        let buckets = duration_seconds_default();
+        assert!(buckets.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    #[should_panic(expected = "factor of exponential buckets")]
+    fn test_invalid_buckets() {
+        let _ = exponential::<4>(1.0, 1.0);
+    }
+
+    #[test]
+    fn test_non_finite_buckets() {
+        assert_eq!(round(f64::INFINITY), f64::INFINITY);
+        assert!(round(f64::NAN).is_nan());
+        assert_eq!(round(f64::MIN_POSITIVE), f64::MIN_POSITIVE);
+        assert_eq!(exponential::<2>(1e-307, 10.0)[0], 1e-307);
+
+        assert!(std::panic::catch_unwind(|| exponential::<400>(1.0, 10.0)).is_err());
+        assert!(std::panic::catch_unwind(|| exponential::<2>(1.0, f64::INFINITY)).is_err());
+        assert!(std::panic::catch_unwind(|| linear::<2>(f64::INFINITY, 1.0)).is_err());
+        assert!(std::panic::catch_unwind(|| linear::<2>(f64::MAX, f64::MAX)).is_err());
+        assert!(std::panic::catch_unwind(|| exponential_range::<2>(1.0, f64::INFINITY)).is_err());
+    }
+}
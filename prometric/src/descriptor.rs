@@ -0,0 +1,42 @@
+//! Metadata of the metrics defined with the `#[metrics]` attribute macro.
+
+use std::borrow::Cow;
+
+use prometheus::proto::MetricType;
+
+/// The definition of a metric of a `#[metrics]` struct, as known at compile time. Options set on
+/// the builder at runtime (static labels, scope and bucket overrides) are not included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricDescriptor {
+    /// The name of the field of the metric.
+    pub field: &'static str,
+    /// The full name of the metric, including the scope and the unit suffix.
+    pub name: Cow<'static, str>,
+    pub kind: MetricType,
+    pub help: &'static str,
+    /// The names of the labels of the accessor.
+    pub labels: &'static [&'static str],
+    pub unit: Option<&'static str>,
+    /// The buckets of a histogram, if they're known at compile time (i.e. the default buckets, a
+    /// literal array or a bucket helper with literal arguments).
+    pub buckets: Option<&'static [f64]>,
+}
+
+/// A struct of metrics generated by the `#[metrics]` attribute macro, which describes its metrics
+/// without registering them, e.g. to generate documentation or validate metric names.
+pub trait MetricsGroup {
+    /// The default scope of the metrics.
+    const SCOPE: &'static str;
+
+    /// The descriptors of the metrics of the struct with the default scope, excluding nested
+    /// metrics.
+    const DESCRIPTORS: &'static [MetricDescriptor];
+
+    /// Returns the descriptors of all metrics with the given scope, including nested metrics.
+    fn descriptors_in(scope: &str) -> Vec<MetricDescriptor>;
+
+    /// Returns the descriptors of all metrics with the default scope, including nested metrics.
+    fn descriptors() -> Vec<MetricDescriptor> {
+        Self::descriptors_in(Self::SCOPE)
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt;
+
+/// An error that occurs while creating and registering a metric.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The metric conflicts with a metric that's already registered with a different type, help
+    /// or labels, or one of its series names collides with another metric.
+    Conflict(String),
+    /// The metric is already registered, and the conflict policy is [`ConflictPolicy::Error`].
+    AlreadyRegistered(String),
+    /// The metric options are invalid, e.g. a quantile outside of `(0, 1)`.
+    InvalidOptions(String),
+    /// An error from the underlying Prometheus client.
+    Prometheus(prometheus::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conflict(msg) | Self::InvalidOptions(msg) => write!(f, "{msg}"),
+            Self::AlreadyRegistered(name) => write!(f, "Metric {name} is already registered"),
+            Self::Prometheus(e) => write!(f, "Prometheus error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Prometheus(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<prometheus::Error> for Error {
+    fn from(e: prometheus::Error) -> Self {
+        Self::Prometheus(e)
+    }
+}
+
+/// What to do when a metric is registered with a registry that already has an instance of it with
+/// the same static label values. Instances with different static label values always share the
+/// registered metric. Set with the generated `on_conflict` builder method.
+///
+/// The policy only applies to instances with the same definition. Registering a metric with a
+/// different type, help, labels, unit or options (e.g. histogram buckets) than the registered one
+/// always fails with [`Error::Conflict`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Fail with [`Error::AlreadyRegistered`].
+    Error,
+    /// Replace the existing instance with the new one. Series of the existing instance are no
+    /// longer exported.
+    #[default]
+    Replace,
+    /// Reuse the existing instance, so that both instances update the same series.
+    Reuse,
+}
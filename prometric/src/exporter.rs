@@ -1,12 +1,20 @@
-use std::{net::SocketAddr, thread};
+use std::{collections::HashMap, net::SocketAddr, thread};
 
 use hyper::{
-    Request, Response, body::Incoming, header::CONTENT_TYPE, server::conn::http1,
+    Request, Response,
+    body::Incoming,
+    header::{ACCEPT, CONTENT_TYPE},
+    server::conn::http1,
     service::service_fn,
 };
 use hyper_util::rt::TokioIo;
 use prometheus::{Encoder, TextEncoder};
 
+mod openmetrics;
+use openmetrics::OpenMetricsEncoder;
+
+use crate::registry;
+
 /// A builder for the Prometheus HTTP exporter.
 pub struct ExporterBuilder {
     registry: Option<prometheus::Registry>,
@@ -151,13 +159,21 @@ async fn serve_req(
     path: String,
     global_prefix: Option<String>,
 ) -> Result<Response<String>, Box<dyn std::error::Error + Send + Sync>> {
-    let encoder = TextEncoder::new();
     let mut metrics = registry.gather();
 
     if req.uri().path() != path {
         return Ok(Response::builder().status(404).body("Not Found".to_string())?);
     }
 
+    // Serve the OpenMetrics format (which includes units) if the client accepts it.
+    let openmetrics = req
+        .headers()
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains("application/openmetrics-text"));
+    let mut units = if openmetrics { registry::units(&registry) } else { HashMap::new() };
+
     // Set the global prefix for the metrics
     if let Some(prefix) = global_prefix {
         metrics.iter_mut().for_each(|metric| {
@@ -166,12 +182,18 @@ async fn serve_req(
                 name.insert_str(0, &prefix);
             };
         });
+        units = units.into_iter().map(|(name, unit)| (format!("{prefix}_{name}"), unit)).collect();
     }
 
-    let body = encoder.encode_to_string(&metrics)?;
+    let (body, format_type) = if openmetrics {
+        let encoder = OpenMetricsEncoder::new(units);
+        (encoder.encode_to_string(&metrics), encoder.format_type().to_owned())
+    } else {
+        let encoder = TextEncoder::new();
+        (encoder.encode_to_string(&metrics)?, encoder.format_type().to_owned())
+    };
 
-    let response =
-        Response::builder().status(200).header(CONTENT_TYPE, encoder.format_type()).body(body)?;
+    let response = Response::builder().status(200).header(CONTENT_TYPE, format_type).body(body)?;
 
     Ok(response)
 }
@@ -0,0 +1,252 @@
+//! An encoder for the [OpenMetrics](https://github.com/prometheus/OpenMetrics) text format, which
+//! unlike the Prometheus text format can carry the unit of a metric.
+
+use std::{collections::HashMap, fmt::Write as _, io};
+
+use prometheus::{
+    Encoder,
+    proto::{LabelPair, Metric, MetricFamily, MetricType},
+};
+
+/// The content type of the OpenMetrics text format.
+pub(crate) const OPENMETRICS_FORMAT: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// An [`Encoder`] for the OpenMetrics text format, which emits a `# UNIT` line for the metrics
+/// with a known unit.
+#[derive(Debug, Default)]
+pub(crate) struct OpenMetricsEncoder {
+    /// The units of the metrics, by metric name.
+    units: HashMap<String, String>,
+}
+
+impl OpenMetricsEncoder {
+    pub(crate) fn new(units: HashMap<String, String>) -> Self {
+        Self { units }
+    }
+
+    pub(crate) fn encode_to_string(&self, families: &[MetricFamily]) -> String {
+        let mut buf = String::new();
+        for family in families.iter().filter(|family| !family.get_metric().is_empty()) {
+            self.encode_family(family, &mut buf);
+        }
+        buf.push_str("# EOF\n");
+        buf
+    }
+
+    fn encode_family(&self, family: &MetricFamily, buf: &mut String) {
+        let name = family.name();
+        let kind = family.get_field_type();
+
+        // Counter families are named without the `_total` suffix, which is added to the samples.
+        let family_name = match kind {
+            MetricType::COUNTER => name.strip_suffix("_total").unwrap_or(name),
+            _ => name,
+        };
+
+        let kind_name = match kind {
+            MetricType::COUNTER => "counter",
+            MetricType::GAUGE => "gauge",
+            MetricType::HISTOGRAM => "histogram",
+            MetricType::SUMMARY => "summary",
+            MetricType::UNTYPED => "unknown",
+        };
+
+        if !family.help().is_empty() {
+            let _ = writeln!(buf, "# HELP {family_name} {}", escape(family.help()));
+        }
+        let _ = writeln!(buf, "# TYPE {family_name} {kind_name}");
+
+        // NOTE: OpenMetrics requires the family name to end with the unit, so units that don't
+        // match the name are left out.
+        if let Some(unit) = self.units.get(name).filter(|unit| {
+            family_name.strip_suffix(unit.as_str()).is_some_and(|n| n.ends_with('_'))
+        }) {
+            let _ = writeln!(buf, "# UNIT {family_name} {unit}");
+        }
+
+        for metric in family.get_metric() {
+            match kind {
+                MetricType::COUNTER => {
+                    let value = metric.get_counter().value();
+                    write_sample(buf, family_name, "_total", metric, None, value);
+                }
+                MetricType::GAUGE => {
+                    write_sample(buf, family_name, "", metric, None, metric.get_gauge().value());
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+
+                    let mut inf_seen = false;
+                    for bucket in histogram.get_bucket() {
+                        let upper_bound = bucket.upper_bound();
+                        inf_seen |= upper_bound == f64::INFINITY;
+
+                        let le = ("le", format_float(upper_bound));
+                        let count = bucket.cumulative_count() as f64;
+                        write_sample(buf, family_name, "_bucket", metric, Some(le), count);
+                    }
+                    if !inf_seen {
+                        let le = ("le", format_float(f64::INFINITY));
+                        let count = histogram.get_sample_count() as f64;
+                        write_sample(buf, family_name, "_bucket", metric, Some(le), count);
+                    }
+
+                    let sum = histogram.get_sample_sum();
+                    let count = histogram.get_sample_count() as f64;
+                    write_sample(buf, family_name, "_sum", metric, None, sum);
+                    write_sample(buf, family_name, "_count", metric, None, count);
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+
+                    for quantile in summary.get_quantile() {
+                        let label = ("quantile", format_float(quantile.quantile()));
+                        write_sample(buf, family_name, "", metric, Some(label), quantile.value());
+                    }
+
+                    let sum = summary.sample_sum();
+                    let count = summary.sample_count() as f64;
+                    write_sample(buf, family_name, "_sum", metric, None, sum);
+                    write_sample(buf, family_name, "_count", metric, None, count);
+                }
+                // NOTE: Like the Prometheus text encoder, untyped metrics aren't supported.
+                MetricType::UNTYPED => {}
+            }
+        }
+    }
+}
+
+impl Encoder for OpenMetricsEncoder {
+    fn encode<W: io::Write>(
+        &self,
+        families: &[MetricFamily],
+        writer: &mut W,
+    ) -> prometheus::Result<()> {
+        writer.write_all(self.encode_to_string(families).as_bytes())?;
+        Ok(())
+    }
+
+    fn format_type(&self) -> &str {
+        OPENMETRICS_FORMAT
+    }
+}
+
+/// Write a single sample with the given name suffix, the labels of the metric and an optional
+/// additional label.
+fn write_sample(
+    buf: &mut String,
+    name: &str,
+    suffix: &str,
+    metric: &Metric,
+    additional_label: Option<(&str, String)>,
+    value: f64,
+) {
+    buf.push_str(name);
+    buf.push_str(suffix);
+    write_labels(buf, metric.get_label(), additional_label);
+
+    let _ = write!(buf, " {}", format_float(value));
+
+    // NOTE: OpenMetrics timestamps are in seconds.
+    let timestamp = metric.timestamp_ms();
+    if timestamp != 0 {
+        let _ = write!(buf, " {}", format_float(timestamp as f64 / 1000.0));
+    }
+
+    buf.push('\n');
+}
+
+fn write_labels(buf: &mut String, pairs: &[LabelPair], additional_label: Option<(&str, String)>) {
+    if pairs.is_empty() && additional_label.is_none() {
+        return;
+    }
+
+    let pairs = pairs.iter().map(|pair| (pair.name(), pair.value()));
+    let additional = additional_label.as_ref().map(|(name, value)| (*name, value.as_str()));
+
+    buf.push('{');
+    for (i, (name, value)) in pairs.chain(additional).enumerate() {
+        if i > 0 {
+            buf.push(',');
+        }
+        let _ = write!(buf, "{name}=\"{}\"", escape(value));
+    }
+    buf.push('}');
+}
+
+/// Format a float as required by OpenMetrics, e.g. `1.0`, `+Inf` or `NaN`.
+fn format_float(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_owned()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_owned()
+    } else if value.is_nan() {
+        "NaN".to_owned()
+    } else {
+        format!("{value:?}")
+    }
+}
+
+/// Escape backslashes, double quotes and newlines in help strings and label values.
+fn escape(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', "\\\"").replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let registry = prometheus::Registry::new();
+
+        let opts = prometheus::Opts::new("requests_total", "The \"total\" requests.");
+        let counter = prometheus::IntCounterVec::new(opts, &["path"]).unwrap();
+        counter.with_label_values(&["/a\\b"]).inc();
+        registry.register(Box::new(counter)).unwrap();
+
+        let opts =
+            prometheus::HistogramOpts::new("duration_seconds", "Duration.").buckets(vec![1.0]);
+        let histogram = prometheus::Histogram::with_opts(opts).unwrap();
+        histogram.observe(0.5);
+        registry.register(Box::new(histogram)).unwrap();
+
+        let units = HashMap::from([("duration_seconds".to_owned(), "seconds".to_owned())]);
+        let output = OpenMetricsEncoder::new(units).encode_to_string(&registry.gather());
+
+        assert_eq!(
+            output,
+            "# HELP duration_seconds Duration.\n\
+            # TYPE duration_seconds histogram\n\
+            # UNIT duration_seconds seconds\n\
+            duration_seconds_bucket{le=\"1.0\"} 1.0\n\
+            duration_seconds_bucket{le=\"+Inf\"} 1.0\n\
+            duration_seconds_sum 0.5\n\
+            duration_seconds_count 1.0\n\
+            # HELP requests The \\\"total\\\" requests.\n\
+            # TYPE requests counter\n\
+            requests_total{path=\"/a\\\\b\"} 1.0\n\
+            # EOF\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let registry = prometheus::Registry::new();
+
+        let opts = prometheus::Opts::new("requests_total", "The \"total\" \\ requests.");
+        let counter = prometheus::IntCounterVec::new(opts, &["path"]).unwrap();
+        counter.with_label_values(&["/\"a\""]).inc();
+        registry.register(Box::new(counter)).unwrap();
+
+        let output = OpenMetricsEncoder::default().encode_to_string(&registry.gather());
+        let families = crate::parse::openmetrics(&output).unwrap();
+
+        assert_eq!(families[0].name(), "requests");
+        assert_eq!(families[0].help(), "The \"total\" \\ requests.");
+        let metric = &families[0].get_metric()[0];
+        assert_eq!(metric.get_label()[0].value(), "/\"a\"");
+        assert_eq!(metric.get_counter().value(), 1.0);
+    }
+}
@@ -0,0 +1,220 @@
+//! Handles to a single label combination of a metric.
+//!
+//! Handles are created with the generated `bind` method on metric accessors. They resolve the
+//! labels once, so updating a handle skips the label hashing and lookup that the accessors perform
+//! on every call. They're cheap to clone and can be shared between threads, which makes them a
+//! good fit for hot loops.
+
+use prometheus::{
+    core::{GenericCounter, GenericGauge, Metric as _},
+    proto,
+};
+
+use crate::{
+    CounterNumber, GaugeNumber, IntoAtomic, IntoSeconds, private, sampler::BoundSampler, summary,
+};
+
+/// A metric handle that records observations, i.e. a [`HistogramHandle`] or a [`SummaryHandle`].
+pub trait Observe: Clone + private::Sealed {
+    /// Record an observation.
+    fn observe(&self, value: f64);
+}
+
+/// A handle to a single label combination of a [`Counter`](crate::Counter).
+pub struct CounterHandle<N: CounterNumber> {
+    inner: GenericCounter<N::Atomic>,
+}
+
+impl<N: CounterNumber> CounterHandle<N> {
+    pub(crate) fn new(inner: GenericCounter<N::Atomic>) -> Self {
+        Self { inner }
+    }
+
+    pub fn inc(&self) {
+        self.inner.inc();
+    }
+
+    pub fn inc_by<V>(&self, value: V)
+    where
+        V: IntoAtomic<N>,
+    {
+        self.inner.inc_by(value.into_atomic());
+    }
+
+    pub fn reset(&self) {
+        self.inner.reset();
+    }
+
+    /// Returns the current value of the counter.
+    pub fn get(&self) -> N {
+        self.inner.get()
+    }
+}
+
+impl<N: CounterNumber> Clone for CounterHandle<N> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<N: CounterNumber> std::fmt::Debug for CounterHandle<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CounterHandle").finish_non_exhaustive()
+    }
+}
+
+/// A handle to a single label combination of a [`Gauge`](crate::Gauge).
+pub struct GaugeHandle<N: GaugeNumber> {
+    inner: GenericGauge<N::Atomic>,
+}
+
+impl<N: GaugeNumber> GaugeHandle<N> {
+    pub(crate) fn new(inner: GenericGauge<N::Atomic>) -> Self {
+        Self { inner }
+    }
+
+    pub fn inc(&self) {
+        self.inner.inc();
+    }
+
+    pub fn dec(&self) {
+        self.inner.dec();
+    }
+
+    pub fn add<V>(&self, value: V)
+    where
+        V: IntoAtomic<N>,
+    {
+        self.inner.add(value.into_atomic());
+    }
+
+    pub fn sub<V>(&self, value: V)
+    where
+        V: IntoAtomic<N>,
+    {
+        self.inner.sub(value.into_atomic());
+    }
+
+    pub fn set<V>(&self, value: V)
+    where
+        V: IntoAtomic<N>,
+    {
+        self.inner.set(value.into_atomic());
+    }
+
+    /// Returns the current value of the gauge.
+    pub fn get(&self) -> N {
+        self.inner.get()
+    }
+}
+
+impl<N: GaugeNumber> Clone for GaugeHandle<N> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<N: GaugeNumber> std::fmt::Debug for GaugeHandle<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GaugeHandle").finish_non_exhaustive()
+    }
+}
+
+/// A handle to a single label combination of a [`Histogram`](crate::Histogram).
+#[derive(Debug, Clone)]
+pub struct HistogramHandle {
+    inner: prometheus::Histogram,
+    sampler: Option<BoundSampler>,
+}
+
+impl HistogramHandle {
+    pub(crate) fn new(inner: prometheus::Histogram, sampler: Option<BoundSampler>) -> Self {
+        Self { inner, sampler }
+    }
+
+    /// Record an observation. A [`Duration`](std::time::Duration) is recorded in seconds, so
+    /// only pass one if the unit of the metric is seconds.
+    pub fn observe<V>(&self, value: V)
+    where
+        V: IntoSeconds,
+    {
+        if self.sampler.as_ref().is_none_or(BoundSampler::sample) {
+            self.inner.observe(value.into_seconds());
+        }
+    }
+
+    /// Returns a snapshot of the recorded observations. For sampled histograms, only the sampled
+    /// observations are included.
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot::new(self.inner.metric().get_histogram())
+    }
+}
+
+impl Observe for HistogramHandle {
+    fn observe(&self, value: f64) {
+        HistogramHandle::observe(self, value);
+    }
+}
+
+/// A snapshot of the observations of a single label combination of a
+/// [`Histogram`](crate::Histogram).
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramSnapshot {
+    /// The number of observations.
+    pub count: u64,
+    /// The sum of all observations.
+    pub sum: f64,
+    /// The upper bounds of the buckets and the cumulative number of observations less than or
+    /// equal to them, in increasing order. The `+Inf` bucket is not included, its count is `count`.
+    pub buckets: Vec<(f64, u64)>,
+}
+
+impl HistogramSnapshot {
+    /// Take a snapshot of a collected histogram.
+    pub(crate) fn new(histogram: &proto::Histogram) -> Self {
+        Self {
+            count: histogram.sample_count(),
+            sum: histogram.sample_sum(),
+            buckets: histogram
+                .get_bucket()
+                .iter()
+                .map(|bucket| (bucket.upper_bound(), bucket.cumulative_count()))
+                .collect(),
+        }
+    }
+
+    /// A snapshot without observations for the given bucket bounds.
+    pub(crate) fn empty(bounds: &[f64]) -> Self {
+        Self { count: 0, sum: 0.0, buckets: bounds.iter().map(|bound| (*bound, 0)).collect() }
+    }
+}
+
+/// A handle to a single label combination of a [`Summary`](crate::Summary).
+#[derive(Debug, Clone)]
+pub struct SummaryHandle {
+    inner: summary::SummaryChild,
+    sampler: Option<BoundSampler>,
+}
+
+impl SummaryHandle {
+    pub(crate) fn new(inner: summary::SummaryChild, sampler: Option<BoundSampler>) -> Self {
+        Self { inner, sampler }
+    }
+
+    /// Record an observation. A [`Duration`](std::time::Duration) is recorded in seconds, so
+    /// only pass one if the unit of the metric is seconds.
+    pub fn observe<V>(&self, value: V)
+    where
+        V: IntoSeconds,
+    {
+        if self.sampler.as_ref().is_none_or(BoundSampler::sample) {
+            self.inner.observe(value.into_seconds());
+        }
+    }
+}
+
+impl Observe for SummaryHandle {
+    fn observe(&self, value: f64) {
+        SummaryHandle::observe(self, value);
+    }
+}
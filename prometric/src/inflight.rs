@@ -0,0 +1,25 @@
+use crate::{GaugeHandle, GaugeNumber};
+
+/// A guard that increments a gauge when created and decrements it again when dropped, so that the
+/// gauge tracks the number of in-flight operations even on early returns.
+///
+/// Guards are created with the generated `track_inflight` method on gauge accessors.
+#[derive(Debug)]
+#[must_use = "The gauge is decremented when the guard is dropped, so it should be bound to a variable"]
+pub struct InflightGuard<N: GaugeNumber> {
+    handle: GaugeHandle<N>,
+}
+
+impl<N: GaugeNumber> InflightGuard<N> {
+    /// Increment the gauge, and return a guard that decrements it when dropped.
+    pub fn new(handle: GaugeHandle<N>) -> Self {
+        handle.inc();
+        Self { handle }
+    }
+}
+
+impl<N: GaugeNumber> Drop for InflightGuard<N> {
+    fn drop(&mut self) {
+        self.handle.dec();
+    }
+}
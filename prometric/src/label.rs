@@ -0,0 +1,251 @@
+//! Conversion of typed values and label sets into label values.
+
+use std::{
+    borrow::{Borrow, Cow},
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+/// A value that can be used as a label value in the generated accessors.
+///
+/// Implemented for strings, integers and `bool`. Enums and other [`Display`](std::fmt::Display)
+/// types can implement it with `#[derive(LabelValue)]` from the `prometric-derive` crate, which
+/// maps unit enum variants to their snake_case name.
+pub trait LabelValue<'a> {
+    /// Convert the value into a label value. Borrows where possible.
+    fn into_label_value(self) -> Cow<'a, str>;
+}
+
+impl<'a> LabelValue<'a> for &'a str {
+    #[inline]
+    fn into_label_value(self) -> Cow<'a, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl<'a> LabelValue<'a> for &&'a str {
+    #[inline]
+    fn into_label_value(self) -> Cow<'a, str> {
+        Cow::Borrowed(*self)
+    }
+}
+
+impl<'a> LabelValue<'a> for &'a String {
+    #[inline]
+    fn into_label_value(self) -> Cow<'a, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl<'a> LabelValue<'a> for String {
+    #[inline]
+    fn into_label_value(self) -> Cow<'a, str> {
+        Cow::Owned(self)
+    }
+}
+
+impl<'a> LabelValue<'a> for Cow<'a, str> {
+    #[inline]
+    fn into_label_value(self) -> Cow<'a, str> {
+        self
+    }
+}
+
+impl<'a> LabelValue<'a> for &'a Cow<'_, str> {
+    #[inline]
+    fn into_label_value(self) -> Cow<'a, str> {
+        Cow::Borrowed(self.as_ref())
+    }
+}
+
+impl<'a> LabelValue<'a> for bool {
+    #[inline]
+    fn into_label_value(self) -> Cow<'a, str> {
+        Cow::Borrowed(if self { "true" } else { "false" })
+    }
+}
+
+impl<'a> LabelValue<'a> for &bool {
+    #[inline]
+    fn into_label_value(self) -> Cow<'a, str> {
+        (*self).into_label_value()
+    }
+}
+
+/// A set of named label values, used as a single accessor argument with
+/// `#[metric(labels = MyLabels)]`.
+///
+/// Implemented with `#[derive(LabelSet)]` from the `prometric-derive` crate on a struct with named
+/// fields, where the field names are the label names and the field values (anything implementing
+/// [`LabelValue`] by reference) are the label values.
+pub trait LabelSet {
+    /// The names of the labels, in the same order as the values passed by
+    /// [`LabelSet::with_label_values`].
+    const LABEL_NAMES: &'static [&'static str];
+
+    /// Call the closure with the label values, in the same order as [`LabelSet::LABEL_NAMES`].
+    fn with_label_values<R>(&self, f: impl FnOnce(&[&str]) -> R) -> R;
+}
+
+/// The static labels of a metric instance, set with the generated builder. They are variable
+/// labels of the underlying metric vector, whose values are bound to the instance, so that
+/// instances with different static label values share the same registered metric.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StaticLabels {
+    /// The label names, sorted.
+    names: Arc<[String]>,
+    /// The label values, in the same order as the names.
+    values: Arc<[String]>,
+}
+
+impl StaticLabels {
+    pub(crate) fn new(labels: HashMap<String, String>) -> Self {
+        let mut labels = labels.into_iter().collect::<Vec<_>>();
+        labels.sort();
+
+        let (names, values): (Vec<_>, Vec<_>) = labels.into_iter().unzip();
+        Self { names: names.into(), values: values.into() }
+    }
+
+    /// The names of all variable labels of the metric: the static labels, followed by `labels`.
+    pub(crate) fn label_names<'a>(&'a self, labels: &[&'a str]) -> Vec<&'a str> {
+        self.names.iter().map(String::as_str).chain(labels.iter().copied()).collect()
+    }
+
+    /// The label values, in the order of the label names.
+    pub(crate) fn values(&self) -> &Arc<[String]> {
+        &self.values
+    }
+
+    /// Returns whether the label values of a series start with the static label values, i.e.
+    /// whether the series belongs to this instance.
+    pub(crate) fn is_prefix_of(&self, values: &[String]) -> bool {
+        values.starts_with(&self.values)
+    }
+
+    /// Call the closure with the values of all variable labels of the metric: the static label
+    /// values, followed by `labels`.
+    #[inline]
+    pub(crate) fn with_values<R>(&self, labels: &[&str], f: impl FnOnce(&[&str]) -> R) -> R {
+        if self.values.is_empty() {
+            return f(labels);
+        }
+
+        let values = self.values.iter().map(String::as_str).chain(labels.iter().copied());
+        with_collected(values, self.values.len() + labels.len(), f)
+    }
+}
+
+/// The number of label values that [`with_collected`] resolves on the stack.
+const INLINE_VALUES: usize = 16;
+
+/// Call the closure with the `len` label values of the iterator. Up to [`INLINE_VALUES`] values are
+/// collected into a buffer on the stack, so that resolving a series doesn't allocate.
+#[inline]
+pub(crate) fn with_collected<'a, R>(
+    values: impl Iterator<Item = &'a str>,
+    len: usize,
+    f: impl FnOnce(&[&str]) -> R,
+) -> R {
+    if len > INLINE_VALUES {
+        return f(&values.collect::<Vec<_>>());
+    }
+
+    let mut buffer = [""; INLINE_VALUES];
+    buffer.iter_mut().zip(values).for_each(|(slot, value)| *slot = value);
+    f(&buffer[..len])
+}
+
+/// The label values of a series, as the key of a map of series. The map is looked up with borrowed
+/// label values through [`Values`], so that finding an existing series doesn't allocate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SeriesKey(Box<[String]>);
+
+impl SeriesKey {
+    pub(crate) fn new(values: &[&str]) -> Self {
+        Self(values.iter().map(|value| value.to_string()).collect())
+    }
+
+    pub(crate) fn values(&self) -> &[String] {
+        &self.0
+    }
+}
+
+/// A list of label values that can be compared with a [`SeriesKey`], e.g. `&[&str]`.
+pub(crate) trait Values {
+    fn len(&self) -> usize;
+
+    fn get(&self, index: usize) -> &str;
+}
+
+impl Values for &[&str] {
+    fn len(&self) -> usize {
+        <[&str]>::len(self)
+    }
+
+    fn get(&self, index: usize) -> &str {
+        self[index]
+    }
+}
+
+impl Values for Box<[String]> {
+    fn len(&self) -> usize {
+        <[String]>::len(self)
+    }
+
+    fn get(&self, index: usize) -> &str {
+        &self[index]
+    }
+}
+
+impl Hash for dyn Values + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        (0..self.len()).for_each(|i| self.get(i).hash(state));
+    }
+}
+
+impl PartialEq for dyn Values + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && (0..self.len()).all(|i| self.get(i) == other.get(i))
+    }
+}
+
+impl Eq for dyn Values + '_ {}
+
+impl<'a> Borrow<dyn Values + 'a> for SeriesKey {
+    fn borrow(&self) -> &(dyn Values + 'a) {
+        &self.0
+    }
+}
+
+impl Hash for SeriesKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // NOTE: Must hash the same as the borrowed values.
+        Borrow::<dyn Values>::borrow(self).hash(state);
+    }
+}
+
+/// Macro to implement `LabelValue` for integer types and references to them.
+macro_rules! impl_label_value {
+    ($($ty:ty),*) => {
+        $(
+            impl<'a> LabelValue<'a> for $ty {
+                #[inline]
+                fn into_label_value(self) -> Cow<'a, str> {
+                    Cow::Owned(self.to_string())
+                }
+            }
+
+            impl<'a> LabelValue<'a> for &$ty {
+                #[inline]
+                fn into_label_value(self) -> Cow<'a, str> {
+                    Cow::Owned(self.to_string())
+                }
+            }
+        )*
+    };
+}
+
+impl_label_value!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
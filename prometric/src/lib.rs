@@ -5,19 +5,86 @@
 //! - [`Counter`]: A counter metric.
 //! - [`Gauge`]: A gauge metric.
 //! - [`Histogram`]: A histogram metric.
+//! - [`Summary`]: A summary metric with client-side quantiles.
 
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
+
+use prometheus::proto::MetricType;
+
+pub mod buckets;
 
 #[cfg(feature = "exporter")]
 pub mod exporter;
 
+pub mod parse;
+
 #[cfg(feature = "process")]
 pub mod process;
 
+#[cfg(feature = "testing")]
+pub mod testing;
+
+mod descriptor;
+pub use descriptor::{MetricDescriptor, MetricsGroup};
+
+mod error;
+pub use error::{ConflictPolicy, Error};
+
+mod handle;
+pub use handle::{
+    CounterHandle, GaugeHandle, HistogramHandle, HistogramSnapshot, Observe, SummaryHandle,
+};
+
+mod sampler;
+use sampler::Sampler;
+
+mod series;
+pub use series::{DEFAULT_OVERFLOW_VALUE, SeriesOptions};
+use series::{FindSeries, RemoveSeries, Series, Tracked};
+
+mod summary;
+pub use summary::{DEFAULT_MAX_AGE, DEFAULT_QUANTILES, Summary};
+
+mod inflight;
+pub use inflight::InflightGuard;
+
+mod label;
+use label::StaticLabels;
+pub use label::{LabelSet, LabelValue};
+
+mod registry;
+use registry::MetricOptions;
+
+mod timer;
+pub use timer::Timer;
+
 /// Sealed trait to prevent outside code from implementing the metric types.
 mod private {
     pub trait Sealed {}
 
+    /// Conversion from the values of collected metrics, which are always `f64`.
+    pub trait FromCollected {
+        fn from_collected(value: f64) -> Self;
+    }
+
+    impl FromCollected for u64 {
+        fn from_collected(value: f64) -> Self {
+            value as u64
+        }
+    }
+
+    impl FromCollected for i64 {
+        fn from_collected(value: f64) -> Self {
+            value as i64
+        }
+    }
+
+    impl FromCollected for f64 {
+        fn from_collected(value: f64) -> Self {
+            value
+        }
+    }
+
     impl Sealed for u64 {}
     impl Sealed for i64 {}
     impl Sealed for f64 {}
@@ -25,6 +92,9 @@ mod private {
     impl Sealed for u32 {}
     impl Sealed for usize {}
     impl Sealed for f32 {}
+
+    impl Sealed for super::HistogramHandle {}
+    impl Sealed for super::SummaryHandle {}
 }
 
 /// Internal conversion trait to allow ergonomic value passing (e.g., `u32`, `usize`).
@@ -68,6 +138,28 @@ impl_into_atomic!(u32 => f64);
 impl_into_atomic!(usize => f64);
 impl_into_atomic!(f32 => f64);
 
+/// Conversion trait for the values of metrics with `unit = "seconds"`, which accept a [`Duration`]
+/// in addition to the numbers accepted by [`IntoAtomic<f64>`].
+///
+/// [`Duration`]: std::time::Duration
+pub trait IntoSeconds {
+    fn into_seconds(self) -> f64;
+}
+
+impl<T: IntoAtomic<f64>> IntoSeconds for T {
+    #[inline]
+    fn into_seconds(self) -> f64 {
+        self.into_atomic()
+    }
+}
+
+impl IntoSeconds for std::time::Duration {
+    #[inline]
+    fn into_seconds(self) -> f64 {
+        self.as_secs_f64()
+    }
+}
+
 /// The default number type for counters.
 pub type CounterDefault = u64;
 
@@ -76,9 +168,9 @@ pub type GaugeDefault = u64;
 
 /// A marker trait for numbers that can be used as counter values.
 /// Supported types: `u64`, `f64`
-pub trait CounterNumber: Sized + 'static + private::Sealed {
+pub trait CounterNumber: Sized + 'static + private::Sealed + private::FromCollected {
     /// The atomic type associated with this number type.
-    type Atomic: prometheus::core::Atomic;
+    type Atomic: prometheus::core::Atomic<T = Self>;
 }
 
 impl CounterNumber for u64 {
@@ -91,9 +183,9 @@ impl CounterNumber for f64 {
 
 /// A marker trait for numbers that can be used as gauge values.
 /// Supported types: `i64`, `f64`, `u64`
-pub trait GaugeNumber: Sized + 'static + private::Sealed {
+pub trait GaugeNumber: Sized + 'static + private::Sealed + private::FromCollected {
     /// The atomic type associated with this number type.
-    type Atomic: prometheus::core::Atomic;
+    type Atomic: prometheus::core::Atomic<T = Self>;
 }
 
 impl GaugeNumber for i64 {
@@ -113,56 +205,130 @@ impl GaugeNumber for u64 {
 #[derive(Debug)]
 pub struct Counter<N: CounterNumber = CounterDefault> {
     inner: prometheus::core::GenericCounterVec<N::Atomic>,
+    series: Arc<Series>,
+    static_labels: StaticLabels,
 }
 
 impl<N: CounterNumber> Clone for Counter<N> {
     fn clone(&self) -> Self {
-        Self { inner: self.inner.clone() }
+        Self {
+            inner: self.inner.clone(),
+            series: self.series.clone(),
+            static_labels: self.static_labels.clone(),
+        }
     }
 }
 
 impl<N: CounterNumber> Counter<N> {
-    /// Create a new counter metric with the given registry, name, help, labels, and const labels.
+    /// Create a new counter metric with the given registry, name, help, labels, and static labels.
+    ///
+    /// # Panics
+    /// Panics if the metric can't be registered. See [`Counter::try_new`].
     pub fn new(
         registry: &prometheus::Registry,
         name: &str,
         help: &str,
         labels: &[&str],
-        const_labels: HashMap<String, String>,
+        static_labels: HashMap<String, String>,
     ) -> Self {
-        let opts = prometheus::Opts::new(name, help).const_labels(const_labels);
-        let metric = prometheus::core::GenericCounterVec::<N::Atomic>::new(opts, labels).unwrap();
-
-        let boxed = Box::new(metric.clone());
-        if let Err(e) = registry.register(boxed.clone()) {
-            let id = format!("{}, Labels: {}", name, labels.join(", "),);
-            // If the metric is already registered, overwrite it.
-            if matches!(e, prometheus::Error::AlreadyReg) {
-                registry
-                    .unregister(boxed.clone())
-                    .unwrap_or_else(|_| panic!("Failed to unregister metric {id}"));
-
-                registry
-                    .register(boxed)
-                    .unwrap_or_else(|_| panic!("Failed to overwrite metric {id}"));
-            } else {
-                panic!("Failed to register metric {id}");
-            }
-        }
+        Self::try_new(
+            registry,
+            name,
+            help,
+            labels,
+            static_labels,
+            None,
+            SeriesOptions::default(),
+            ConflictPolicy::default(),
+        )
+        .unwrap_or_else(|e| panic!("{e}"))
+    }
 
-        Self { inner: metric }
+    /// Create a new counter metric with the given registry, name, help, labels, and static labels.
+    /// If a metric with the same name is already registered, the conflict policy decides what
+    /// happens. The unit, if any, is exported in the `# UNIT` line of the OpenMetrics format.
+    ///
+    /// The static labels are prepended to the labels of the underlying metric vector, and their
+    /// values are bound to this instance. Instances with different static label values therefore
+    /// share the registered metric, and the conflict policy only applies to instances with the same
+    /// static label values. The series options, e.g. the cardinality limit, apply to the metric as
+    /// a whole, and are shared by all instances.
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_new(
+        registry: &prometheus::Registry,
+        name: &str,
+        help: &str,
+        labels: &[&str],
+        static_labels: HashMap<String, String>,
+        unit: Option<&str>,
+        series: SeriesOptions,
+        on_conflict: ConflictPolicy,
+    ) -> Result<Self, Error> {
+        let static_labels = StaticLabels::new(static_labels);
+        let opts = prometheus::Opts::new(name, help);
+        let metric = prometheus::core::GenericCounterVec::<N::Atomic>::new(
+            opts,
+            &static_labels.label_names(labels),
+        )?;
+        let metric = Tracked::new(metric, None, Series::new(registry, name, series)?);
+        let Tracked { collector: metric, series, .. } = metric.register(
+            registry,
+            MetricType::COUNTER,
+            MetricOptions::new(unit),
+            &static_labels,
+            on_conflict,
+        )?;
+
+        Ok(Self { inner: metric, series, static_labels })
     }
 
     pub fn inc(&self, labels: &[&str]) {
-        self.inner.with_label_values(labels).inc();
+        self.child(labels).inc();
     }
 
     pub fn inc_by(&self, labels: &[&str], value: <N::Atomic as prometheus::core::Atomic>::T) {
-        self.inner.with_label_values(labels).inc_by(value);
+        self.child(labels).inc_by(value);
     }
 
     pub fn reset(&self, labels: &[&str]) {
-        self.inner.with_label_values(labels).reset();
+        self.child(labels).reset();
+    }
+
+    /// Returns the current value of the counter for the given label values, or zero if the series
+    /// doesn't exist. Reading doesn't create the series, count against the cardinality limit or
+    /// refresh its TTL.
+    ///
+    /// To not create the series, this collects and scans all series of the metric, so its cost
+    /// grows with the number of series. For frequent reads, e.g. on hot paths, read a handle
+    /// returned by [`Counter::bind`] instead, which creates the series once and reads it directly.
+    pub fn get(&self, labels: &[&str]) -> N {
+        self.static_labels.with_values(labels, |values| {
+            let value = self.inner.find_series(values).map(|metric| metric.get_counter().value());
+            N::from_collected(value.unwrap_or_default())
+        })
+    }
+
+    /// Remove the series with the given label values.
+    pub fn remove(&self, labels: &[&str]) {
+        self.static_labels
+            .with_values(labels, |values| self.series.remove(&self.inner, None, values));
+    }
+
+    /// Remove all series of this instance, i.e. with its static label values.
+    pub fn clear(&self) {
+        self.series.clear(&self.inner, None, &self.static_labels);
+    }
+
+    /// Resolve the counter for the given label values.
+    pub fn bind(&self, labels: &[&str]) -> CounterHandle<N> {
+        CounterHandle::new(self.child(labels))
+    }
+
+    /// Resolve the counter of the underlying vector for the static and given label values.
+    #[inline]
+    fn child(&self, labels: &[&str]) -> prometheus::core::GenericCounter<N::Atomic> {
+        self.series
+            .with_values(&self.static_labels, labels, |labels| self.inner.with_label_values(labels))
     }
 }
 
@@ -171,64 +337,133 @@ impl<N: CounterNumber> Counter<N> {
 #[derive(Debug)]
 pub struct Gauge<N: GaugeNumber = GaugeDefault> {
     inner: prometheus::core::GenericGaugeVec<N::Atomic>,
+    series: Arc<Series>,
+    static_labels: StaticLabels,
 }
 
 impl<N: GaugeNumber> Clone for Gauge<N> {
     fn clone(&self) -> Self {
-        Self { inner: self.inner.clone() }
+        Self {
+            inner: self.inner.clone(),
+            series: self.series.clone(),
+            static_labels: self.static_labels.clone(),
+        }
     }
 }
 
 impl<N: GaugeNumber> Gauge<N> {
-    /// Create a new gauge metric with the given registry, name, help, labels, and const labels.
+    /// Create a new gauge metric with the given registry, name, help, labels, and static labels.
+    ///
+    /// # Panics
+    /// Panics if the metric can't be registered. See [`Gauge::try_new`].
     pub fn new(
         registry: &prometheus::Registry,
         name: &str,
         help: &str,
         labels: &[&str],
-        const_labels: HashMap<String, String>,
+        static_labels: HashMap<String, String>,
     ) -> Self {
-        let opts = prometheus::Opts::new(name, help).const_labels(const_labels);
-        let metric = prometheus::core::GenericGaugeVec::<N::Atomic>::new(opts, labels).unwrap();
-
-        let boxed = Box::new(metric.clone());
-        if let Err(e) = registry.register(boxed.clone()) {
-            let id = format!("{}, Labels: {}", name, labels.join(", "),);
-            // If the metric is already registered, overwrite it.
-            if matches!(e, prometheus::Error::AlreadyReg) {
-                registry
-                    .unregister(boxed.clone())
-                    .unwrap_or_else(|_| panic!("Failed to unregister metric {id}"));
-
-                registry
-                    .register(boxed)
-                    .unwrap_or_else(|_| panic!("Failed to overwrite metric {id}"));
-            } else {
-                panic!("Failed to register metric {id}");
-            }
-        }
+        Self::try_new(
+            registry,
+            name,
+            help,
+            labels,
+            static_labels,
+            None,
+            SeriesOptions::default(),
+            ConflictPolicy::default(),
+        )
+        .unwrap_or_else(|e| panic!("{e}"))
+    }
 
-        Self { inner: metric }
+    /// Create a new gauge metric with the given registry, name, help, labels, and static labels.
+    /// If a metric with the same name is already registered, the conflict policy decides what
+    /// happens. See [`Counter::try_new`] for how static labels, units and series options are
+    /// handled.
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_new(
+        registry: &prometheus::Registry,
+        name: &str,
+        help: &str,
+        labels: &[&str],
+        static_labels: HashMap<String, String>,
+        unit: Option<&str>,
+        series: SeriesOptions,
+        on_conflict: ConflictPolicy,
+    ) -> Result<Self, Error> {
+        let static_labels = StaticLabels::new(static_labels);
+        let opts = prometheus::Opts::new(name, help);
+        let metric = prometheus::core::GenericGaugeVec::<N::Atomic>::new(
+            opts,
+            &static_labels.label_names(labels),
+        )?;
+        let metric = Tracked::new(metric, None, Series::new(registry, name, series)?);
+        let Tracked { collector: metric, series, .. } = metric.register(
+            registry,
+            MetricType::GAUGE,
+            MetricOptions::new(unit),
+            &static_labels,
+            on_conflict,
+        )?;
+
+        Ok(Self { inner: metric, series, static_labels })
     }
 
     pub fn inc(&self, labels: &[&str]) {
-        self.inner.with_label_values(labels).inc();
+        self.child(labels).inc();
     }
 
     pub fn dec(&self, labels: &[&str]) {
-        self.inner.with_label_values(labels).dec();
+        self.child(labels).dec();
     }
 
     pub fn add(&self, labels: &[&str], value: <N::Atomic as prometheus::core::Atomic>::T) {
-        self.inner.with_label_values(labels).add(value);
+        self.child(labels).add(value);
     }
 
     pub fn sub(&self, labels: &[&str], value: <N::Atomic as prometheus::core::Atomic>::T) {
-        self.inner.with_label_values(labels).sub(value);
+        self.child(labels).sub(value);
     }
 
     pub fn set(&self, labels: &[&str], value: <N::Atomic as prometheus::core::Atomic>::T) {
-        self.inner.with_label_values(labels).set(value);
+        self.child(labels).set(value);
+    }
+
+    /// Returns the current value of the gauge for the given label values, or zero if the series
+    /// doesn't exist. Reading doesn't create the series, count against the cardinality limit or
+    /// refresh its TTL.
+    ///
+    /// To not create the series, this collects and scans all series of the metric, so its cost
+    /// grows with the number of series. For frequent reads, e.g. on hot paths, read a handle
+    /// returned by [`Gauge::bind`] instead, which creates the series once and reads it directly.
+    pub fn get(&self, labels: &[&str]) -> N {
+        self.static_labels.with_values(labels, |values| {
+            let value = self.inner.find_series(values).map(|metric| metric.get_gauge().value());
+            N::from_collected(value.unwrap_or_default())
+        })
+    }
+
+    /// Remove the series with the given label values.
+    pub fn remove(&self, labels: &[&str]) {
+        self.static_labels
+            .with_values(labels, |values| self.series.remove(&self.inner, None, values));
+    }
+
+    /// Remove all series of this instance, i.e. with its static label values.
+    pub fn clear(&self) {
+        self.series.clear(&self.inner, None, &self.static_labels);
+    }
+
+    /// Resolve the gauge for the given label values.
+    pub fn bind(&self, labels: &[&str]) -> GaugeHandle<N> {
+        GaugeHandle::new(self.child(labels))
+    }
+
+    /// Resolve the gauge of the underlying vector for the static and given label values.
+    #[inline]
+    fn child(&self, labels: &[&str]) -> prometheus::core::GenericGauge<N::Atomic> {
+        self.series
+            .with_values(&self.static_labels, labels, |labels| self.inner.with_label_values(labels))
     }
 }
 
@@ -236,49 +471,133 @@ impl<N: GaugeNumber> Gauge<N> {
 #[derive(Debug)]
 pub struct Histogram {
     inner: prometheus::HistogramVec,
+    series: Arc<Series>,
+    static_labels: StaticLabels,
+    sampler: Option<Sampler>,
+    /// The upper bounds of the buckets, without the `+Inf` bucket.
+    bounds: Arc<[f64]>,
 }
 
 impl Clone for Histogram {
     fn clone(&self) -> Self {
-        Self { inner: self.inner.clone() }
+        Self {
+            inner: self.inner.clone(),
+            series: self.series.clone(),
+            static_labels: self.static_labels.clone(),
+            sampler: self.sampler.clone(),
+            bounds: self.bounds.clone(),
+        }
     }
 }
 
 impl Histogram {
+    /// Create a new histogram metric with the given registry, name, help, labels, static labels and
+    /// buckets. All observations are recorded; use [`Histogram::try_new`] to sample them.
+    ///
+    /// # Panics
+    /// Panics if the metric can't be registered. See [`Histogram::try_new`].
     pub fn new<B: Into<Vec<f64>>>(
         registry: &prometheus::Registry,
         name: &str,
         help: &str,
         labels: &[&str],
-        const_labels: HashMap<String, String>,
+        static_labels: HashMap<String, String>,
         buckets: Option<B>,
     ) -> Self {
-        let buckets = buckets.map(Into::into).unwrap_or(prometheus::DEFAULT_BUCKETS.to_vec());
-        let opts =
-            prometheus::HistogramOpts::new(name, help).const_labels(const_labels).buckets(buckets);
-        let metric = prometheus::HistogramVec::new(opts, labels).unwrap();
-
-        let boxed = Box::new(metric.clone());
-        if let Err(e) = registry.register(boxed.clone()) {
-            let id = format!("{}, Labels: {}", name, labels.join(", "),);
-            // If the metric is already registered, overwrite it.
-            if matches!(e, prometheus::Error::AlreadyReg) {
-                registry
-                    .unregister(boxed.clone())
-                    .unwrap_or_else(|_| panic!("Failed to unregister metric {id}"));
-
-                registry
-                    .register(boxed)
-                    .unwrap_or_else(|_| panic!("Failed to overwrite metric {id}"));
-            } else {
-                panic!("Failed to register metric {id}");
-            }
-        }
+        Self::try_new(
+            registry,
+            name,
+            help,
+            labels,
+            static_labels,
+            buckets,
+            None,
+            None,
+            SeriesOptions::default(),
+            ConflictPolicy::default(),
+        )
+        .unwrap_or_else(|e| panic!("{e}"))
+    }
 
-        Self { inner: metric }
+    /// Create a new histogram metric with the given registry, name, help, labels, static labels,
+    /// buckets and sample rate. If a metric with the same name is already registered, the conflict
+    /// policy decides what happens. See [`Counter::try_new`] for how static labels, units and series
+    /// options are handled.
+    ///
+    /// If a sample rate is provided, only that fraction of observations is recorded, and the total
+    /// number of observations is tracked in a companion `<name>_observations_total` counter.
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_new<B: Into<Vec<f64>>>(
+        registry: &prometheus::Registry,
+        name: &str,
+        help: &str,
+        labels: &[&str],
+        static_labels: HashMap<String, String>,
+        buckets: Option<B>,
+        sample: Option<f64>,
+        unit: Option<&str>,
+        series: SeriesOptions,
+        on_conflict: ConflictPolicy,
+    ) -> Result<Self, Error> {
+        let static_labels = StaticLabels::new(static_labels);
+        let label_names = static_labels.label_names(labels);
+
+        let sampler = sample.map(|rate| Sampler::new(name, &label_names, rate)).transpose()?;
+
+        let buckets = buckets.map(Into::into).unwrap_or(prometheus::DEFAULT_BUCKETS.to_vec());
+        let bounds = buckets.iter().copied().filter(|bound| *bound != f64::INFINITY).collect();
+        let options =
+            MetricOptions { buckets: Some(buckets.clone()), sample, ..MetricOptions::new(unit) };
+        let opts = prometheus::HistogramOpts::new(name, help).buckets(buckets);
+        let metric = prometheus::HistogramVec::new(opts, &label_names)?;
+        let metric = Tracked::new(metric, sampler, Series::new(registry, name, series)?);
+        let Tracked { collector: metric, sampler, series, .. } = metric.register(
+            registry,
+            MetricType::HISTOGRAM,
+            options,
+            &static_labels,
+            on_conflict,
+        )?;
+
+        Ok(Self { inner: metric, series, static_labels, sampler, bounds })
     }
 
     pub fn observe(&self, labels: &[&str], value: f64) {
-        self.inner.with_label_values(labels).observe(value);
+        self.bind(labels).observe(value);
+    }
+
+    /// Returns a snapshot of the observations for the given label values, which is empty if the
+    /// series doesn't exist. Reading doesn't create the series, count against the cardinality limit
+    /// or refresh its TTL.
+    ///
+    /// To not create the series, this collects and scans all series of the metric, so its cost
+    /// grows with the number of series. For frequent reads, e.g. on hot paths, read a handle
+    /// returned by [`Histogram::bind`] instead, which creates the series once and reads it
+    /// directly.
+    pub fn snapshot(&self, labels: &[&str]) -> HistogramSnapshot {
+        self.static_labels.with_values(labels, |values| match self.inner.find_series(values) {
+            Some(metric) => HistogramSnapshot::new(metric.get_histogram()),
+            None => HistogramSnapshot::empty(&self.bounds),
+        })
+    }
+
+    /// Remove the series with the given label values.
+    pub fn remove(&self, labels: &[&str]) {
+        self.static_labels.with_values(labels, |values| {
+            self.series.remove(&self.inner, self.sampler.as_ref(), values);
+        });
+    }
+
+    /// Remove all series of this instance, i.e. with its static label values.
+    pub fn clear(&self) {
+        self.series.clear(&self.inner, self.sampler.as_ref(), &self.static_labels);
+    }
+
+    /// Resolve the histogram for the given label values.
+    pub fn bind(&self, labels: &[&str]) -> HistogramHandle {
+        self.series.with_values(&self.static_labels, labels, |labels| {
+            let sampler = self.sampler.as_ref().map(|sampler| sampler.bind(labels));
+            HistogramHandle::new(self.inner.with_label_values(labels), sampler)
+        })
     }
 }
@@ -0,0 +1,669 @@
+//! Parsers for the exposition formats.
+
+use std::{collections::HashMap, fmt};
+
+use prometheus::proto::{self, LabelPair, Metric, MetricFamily, MetricType};
+
+/// An error that occurs while parsing the exposition format, with the line it occurred on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The 1-based line number.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Parse error on line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parse the Prometheus text exposition format into metric families, like the ones returned by
+/// [`prometheus::Registry::gather`].
+///
+/// - `HELP` and `TYPE` lines set the help and type of a family. Samples without a `TYPE` line are
+///   untyped. `UNIT` lines are validated but not included in the families (which have no unit).
+/// - Histograms and summaries are assembled from their `_bucket`, `_sum` and `_count` samples and
+///   quantiles. The `+Inf` bucket is not included in the buckets, as in the gathered families.
+/// - Timestamps are integers in milliseconds.
+///
+/// Families without samples are left out. Use [`openmetrics`] for the OpenMetrics text format.
+///
+/// ```rust
+/// let families = prometric::parse::text(
+///     r#"
+///     ## HELP requests_total The total number of requests.
+///     ## TYPE requests_total counter
+///     requests_total{method="GET"} 3
+///     "#,
+/// )
+/// .unwrap();
+///
+/// assert_eq!(families[0].name(), "requests_total");
+/// assert_eq!(families[0].get_metric()[0].get_counter().value(), 3.0);
+/// ```
+pub fn text(input: &str) -> Result<Vec<MetricFamily>, ParseError> {
+    parse(input, Format::Text)
+}
+
+/// Parse the OpenMetrics text format into metric families, like the ones returned by
+/// [`prometheus::Registry::gather`].
+///
+/// It's parsed like the Prometheus text format (see [`text`]), except that:
+///
+/// - Counter samples have the `_total` suffix, which the family name doesn't.
+/// - `_created` samples of counters, histograms and summaries are validated but not included in the
+///   families (which have no creation time).
+/// - `unknown` families are untyped, `info` families are gauges with the `_info` suffix, and
+///   `stateset` families are gauges. `gaugehistogram` families aren't supported.
+/// - Timestamps are in seconds, and may be fractional.
+/// - Parsing stops at `# EOF`.
+///
+/// ```rust
+/// let families = prometric::parse::openmetrics(
+///     "# TYPE requests counter\nrequests_total 3 1700000000\n# EOF\n",
+/// )
+/// .unwrap();
+///
+/// assert_eq!(families[0].name(), "requests");
+/// assert_eq!(families[0].get_metric()[0].timestamp_ms(), 1_700_000_000_000);
+/// ```
+pub fn openmetrics(input: &str) -> Result<Vec<MetricFamily>, ParseError> {
+    parse(input, Format::OpenMetrics)
+}
+
+/// A text format that can be parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Format {
+    #[default]
+    Text,
+    OpenMetrics,
+}
+
+fn parse(input: &str, format: Format) -> Result<Vec<MetricFamily>, ParseError> {
+    let mut parser = Parser { format, ..Default::default() };
+
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let result = if let Some(comment) = line.strip_prefix('#') {
+            parser.comment(comment.trim_start())
+        } else {
+            parser.sample(line)
+        };
+        result.map_err(|message| ParseError { line: i + 1, message })?;
+
+        if parser.eof {
+            break;
+        }
+    }
+
+    Ok(parser.finish())
+}
+
+/// A metric family that's being parsed.
+#[derive(Debug, Default)]
+struct Family {
+    name: String,
+    help: Option<String>,
+    kind: Option<MetricType>,
+    /// Whether this is an OpenMetrics info family, whose samples have the `_info` suffix.
+    info: bool,
+    metrics: Vec<Metric>,
+    /// The index of the metric of each series, by its sorted labels.
+    series: HashMap<Vec<(String, String)>, usize>,
+}
+
+impl Family {
+    /// Returns the metric of the series with the given labels, creating it if it doesn't exist.
+    fn metric(&mut self, labels: Vec<(String, String)>, timestamp: Option<i64>) -> &mut Metric {
+        let mut key = labels.clone();
+        key.sort();
+
+        let index = *self.series.entry(key).or_insert_with(|| {
+            let mut metric = Metric::default();
+            metric.set_label(
+                labels
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let mut pair = LabelPair::default();
+                        pair.set_name(name);
+                        pair.set_value(value);
+                        pair
+                    })
+                    .collect(),
+            );
+            self.metrics.push(metric);
+            self.metrics.len() - 1
+        });
+
+        let metric = &mut self.metrics[index];
+        if let Some(timestamp) = timestamp {
+            metric.set_timestamp_ms(timestamp);
+        }
+        metric
+    }
+}
+
+#[derive(Debug, Default)]
+struct Parser {
+    format: Format,
+    families: Vec<Family>,
+    /// The index of each family, by name.
+    by_name: HashMap<String, usize>,
+    eof: bool,
+}
+
+impl Parser {
+    fn family(&mut self, name: &str) -> &mut Family {
+        let index = *self.by_name.entry(name.to_owned()).or_insert_with(|| {
+            self.families.push(Family { name: name.to_owned(), ..Default::default() });
+            self.families.len() - 1
+        });
+
+        &mut self.families[index]
+    }
+
+    /// Parse a comment line, without the leading `#`.
+    fn comment(&mut self, comment: &str) -> Result<(), String> {
+        if self.format == Format::OpenMetrics && comment == "EOF" {
+            self.eof = true;
+            return Ok(());
+        }
+
+        let (keyword, rest) = split_token(comment);
+        if !matches!(keyword, "HELP" | "TYPE" | "UNIT") {
+            // Other comments are ignored.
+            return Ok(());
+        }
+
+        let (name, rest) = split_token(rest);
+        if !is_valid_name(name) {
+            return Err(format!("Invalid metric name `{name}` in {keyword} line"));
+        }
+
+        match keyword {
+            "HELP" => {
+                // NOTE: Only OpenMetrics escapes double quotes in help strings.
+                let quotes = self.format == Format::OpenMetrics;
+                let family = self.family(name);
+                if family.help.is_some() {
+                    return Err(format!("Duplicate HELP line for metric `{name}`"));
+                }
+                family.help = Some(unescape(rest, quotes)?);
+            }
+            "TYPE" => {
+                let kind = match (self.format, rest) {
+                    (_, "counter") => MetricType::COUNTER,
+                    (_, "gauge") => MetricType::GAUGE,
+                    (_, "histogram") => MetricType::HISTOGRAM,
+                    (_, "summary") => MetricType::SUMMARY,
+                    (Format::Text, "untyped") | (Format::OpenMetrics, "unknown") => {
+                        MetricType::UNTYPED
+                    }
+                    (Format::OpenMetrics, "info" | "stateset") => MetricType::GAUGE,
+                    (_, other) => return Err(format!("Unsupported metric type `{other}`")),
+                };
+                let info = self.format == Format::OpenMetrics && rest == "info";
+
+                let family = self.family(name);
+                if family.kind.is_some() {
+                    return Err(format!("Duplicate TYPE line for metric `{name}`"));
+                }
+                if !family.metrics.is_empty() {
+                    return Err(format!("TYPE line for metric `{name}` after its samples"));
+                }
+                family.kind = Some(kind);
+                family.info = info;
+            }
+            _ => {
+                if !rest.is_empty() && !is_valid_name(rest) {
+                    return Err(format!("Invalid unit `{rest}` for metric `{name}`"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the name of the family that the sample with the given name belongs to, and the
+    /// suffix of the sample name.
+    fn resolve<'a>(&self, name: &'a str) -> (&'a str, &'a str) {
+        if self.by_name.contains_key(name) {
+            return (name, "");
+        }
+
+        let openmetrics = self.format == Format::OpenMetrics;
+        for suffix in ["_bucket", "_sum", "_count", "_total", "_created", "_info"] {
+            let Some(base) = name.strip_suffix(suffix) else {
+                continue;
+            };
+
+            let Some(family) = self.by_name.get(base).map(|index| &self.families[*index]) else {
+                continue;
+            };
+            let kind = family.kind;
+            let matches = match suffix {
+                "_bucket" => kind == Some(MetricType::HISTOGRAM),
+                "_total" => openmetrics && kind == Some(MetricType::COUNTER),
+                "_created" => {
+                    openmetrics
+                        && matches!(
+                            kind,
+                            Some(MetricType::COUNTER | MetricType::HISTOGRAM | MetricType::SUMMARY)
+                        )
+                }
+                "_info" => family.info,
+                _ => matches!(kind, Some(MetricType::HISTOGRAM | MetricType::SUMMARY)),
+            };
+            if matches {
+                return (base, suffix);
+            }
+        }
+
+        (name, "")
+    }
+
+    /// Parse a sample line.
+    fn sample(&mut self, line: &str) -> Result<(), String> {
+        let name_end = line.find(|c: char| c == '{' || c.is_whitespace()).unwrap_or(line.len());
+        let name = &line[..name_end];
+        if !is_valid_name(name) {
+            return Err(format!("Invalid metric name `{name}`"));
+        }
+
+        let mut rest = &line[name_end..];
+        let mut labels = Vec::new();
+        if let Some(inner) = rest.strip_prefix('{') {
+            rest = parse_labels(inner, &mut labels)?;
+        }
+
+        let (value, rest) = split_token(rest.trim_start());
+        let value = parse_float(value)?;
+        let (timestamp, rest) = split_token(rest);
+        if !rest.is_empty() {
+            return Err(format!("Unexpected `{rest}` after the sample"));
+        }
+        let timestamp =
+            (!timestamp.is_empty()).then(|| parse_timestamp(timestamp, self.format)).transpose()?;
+
+        let (family_name, suffix) = self.resolve(name);
+        let family = self.family(family_name);
+        if family.info && suffix != "_info" {
+            return Err(format!("Invalid sample `{name}` for info"));
+        }
+        if suffix == "_created" {
+            // NOTE: The families have no creation time, so the value is only validated.
+            return Ok(());
+        }
+
+        match family.kind.unwrap_or(MetricType::UNTYPED) {
+            MetricType::COUNTER => {
+                let metric = family.metric(labels, timestamp);
+                metric.counter.mut_or_insert_default().set_value(value);
+            }
+            MetricType::GAUGE => {
+                let metric = family.metric(labels, timestamp);
+                metric.gauge.mut_or_insert_default().set_value(value);
+            }
+            MetricType::UNTYPED => {
+                let metric = family.metric(labels, timestamp);
+                metric.untyped.mut_or_insert_default().set_value(value);
+            }
+            MetricType::HISTOGRAM => {
+                let le = take_label(&mut labels, "le", suffix == "_bucket")?;
+                let metric = family.metric(labels, timestamp);
+                let histogram = metric.histogram.mut_or_insert_default();
+
+                match (suffix, le) {
+                    ("_bucket", Some(f64::INFINITY)) => histogram.set_sample_count(count(value)?),
+                    ("_bucket", Some(upper_bound)) => {
+                        let mut bucket = proto::Bucket::default();
+                        bucket.set_upper_bound(upper_bound);
+                        bucket.set_cumulative_count(count(value)?);
+                        histogram.bucket.push(bucket);
+                    }
+                    ("_sum", _) => histogram.set_sample_sum(value),
+                    ("_count", _) => histogram.set_sample_count(count(value)?),
+                    _ => return Err(format!("Invalid sample `{name}` for histogram")),
+                }
+            }
+            MetricType::SUMMARY => {
+                let quantile = take_label(&mut labels, "quantile", suffix.is_empty())?;
+                let metric = family.metric(labels, timestamp);
+                let summary = metric.summary.mut_or_insert_default();
+
+                match (suffix, quantile) {
+                    ("", Some(q)) => {
+                        let mut quantile = proto::Quantile::default();
+                        quantile.set_quantile(q);
+                        quantile.set_value(value);
+                        summary.quantile.push(quantile);
+                    }
+                    ("_sum", _) => summary.set_sample_sum(value),
+                    ("_count", _) => summary.set_sample_count(count(value)?),
+                    _ => return Err(format!("Invalid sample `{name}` for summary")),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Vec<MetricFamily> {
+        self.families
+            .into_iter()
+            .filter(|family| !family.metrics.is_empty())
+            .map(|family| {
+                let mut proto = MetricFamily::default();
+                if family.info {
+                    proto.set_name(format!("{}_info", family.name));
+                } else {
+                    proto.set_name(family.name);
+                }
+                proto.set_help(family.help.unwrap_or_default());
+                proto.set_field_type(family.kind.unwrap_or(MetricType::UNTYPED));
+                proto.set_metric(family.metrics);
+                proto
+            })
+            .collect()
+    }
+}
+
+/// Parse the labels after the opening brace into `labels`, and return the rest of the line after
+/// the closing brace.
+fn parse_labels<'a>(
+    mut rest: &'a str,
+    labels: &mut Vec<(String, String)>,
+) -> Result<&'a str, String> {
+    loop {
+        rest = rest.trim_start();
+        if let Some(rest) = rest.strip_prefix('}') {
+            return Ok(rest);
+        }
+
+        let (name, after) = rest.split_once('=').ok_or("Expected `=` after the label name")?;
+        let name = name.trim();
+        if !is_valid_label_name(name) {
+            return Err(format!("Invalid label name `{name}`"));
+        }
+        if labels.iter().any(|(existing, _)| existing == name) {
+            return Err(format!("Duplicate label `{name}`"));
+        }
+
+        let after = after.trim_start().strip_prefix('"').ok_or("Expected a quoted label value")?;
+        let end = find_closing_quote(after).ok_or("Unterminated label value")?;
+        labels.push((name.to_owned(), unescape(&after[..end], true)?));
+
+        rest = after[end + 1..].trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after;
+        } else if !rest.starts_with('}') {
+            return Err("Expected `,` or `}` after the label value".to_owned());
+        }
+    }
+}
+
+/// Returns the index of the first unescaped double quote.
+fn find_closing_quote(value: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' if !escaped => return Some(i),
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+
+    None
+}
+
+/// Unescape backslashes and newlines, and double quotes if `quotes` is set (in label values, and
+/// in OpenMetrics help strings).
+fn unescape(value: &str, quotes: bool) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('"') if quotes => unescaped.push('"'),
+            // NOTE: Unknown escape sequences are kept as is, as in the Prometheus parser.
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => return Err("Trailing backslash".to_owned()),
+        }
+    }
+
+    Ok(unescaped)
+}
+
+/// Remove the label with the given name and parse its value. Returns an error if it's required
+/// but missing.
+fn take_label(
+    labels: &mut Vec<(String, String)>,
+    name: &str,
+    required: bool,
+) -> Result<Option<f64>, String> {
+    match labels.iter().position(|(label, _)| label == name) {
+        Some(index) => parse_float(&labels.remove(index).1).map(Some),
+        None if required => Err(format!("Missing `{name}` label")),
+        None => Ok(None),
+    }
+}
+
+/// Split off the first whitespace-separated token.
+fn split_token(s: &str) -> (&str, &str) {
+    match s.split_once(char::is_whitespace) {
+        Some((token, rest)) => (token, rest.trim_start()),
+        None => (s, ""),
+    }
+}
+
+fn parse_float(value: &str) -> Result<f64, String> {
+    match value {
+        "+Inf" => Ok(f64::INFINITY),
+        "-Inf" => Ok(f64::NEG_INFINITY),
+        "NaN" => Ok(f64::NAN),
+        "" => Err("Missing sample value".to_owned()),
+        _ => value.parse().map_err(|_| format!("Invalid value `{value}`")),
+    }
+}
+
+/// Parse a timestamp into milliseconds. Timestamps are integers in milliseconds in the Prometheus
+/// text format, and in seconds in OpenMetrics.
+fn parse_timestamp(timestamp: &str, format: Format) -> Result<i64, String> {
+    let millis = match format {
+        Format::Text => timestamp.parse().ok(),
+        Format::OpenMetrics => timestamp
+            .parse::<f64>()
+            .ok()
+            .filter(|seconds| seconds.is_finite())
+            .map(|seconds| (seconds * 1000.0).round() as i64),
+    };
+
+    millis.ok_or_else(|| format!("Invalid timestamp `{timestamp}`"))
+}
+
+/// Convert a sample value to a count.
+fn count(value: f64) -> Result<u64, String> {
+    if value >= 0.0 && value.fract() == 0.0 {
+        Ok(value as u64)
+    } else {
+        Err(format!("Invalid count `{value}`"))
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn is_valid_label_name(name: &str) -> bool {
+    is_valid_name(name) && !name.contains(':')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{Counter, Gauge, Histogram, Summary};
+
+    #[test]
+    fn test_round_trip() {
+        let registry = prometheus::Registry::new();
+        let static_labels = HashMap::from([("host".to_owned(), "a \"b\"\\c\nd".to_owned())]);
+
+        let counter = Counter::<u64>::new(
+            &registry,
+            "requests_total",
+            "Requests\nand \\ more",
+            &["method"],
+            static_labels.clone(),
+        );
+        let gauge = Gauge::<f64>::new(&registry, "temperature", "Temp", &[], static_labels.clone());
+        let histogram = Histogram::new(
+            &registry,
+            "latency",
+            "Latency",
+            &["path"],
+            static_labels.clone(),
+            Some([0.1, 1.0]),
+        );
+        let summary = Summary::new(
+            &registry,
+            "size",
+            "Size",
+            &[],
+            static_labels,
+            None::<Vec<f64>>,
+            None,
+            None,
+        );
+
+        counter.inc_by(&["GET"], 3);
+        counter.inc(&["POST"]);
+        gauge.set(&[], -1.5);
+        histogram.observe(&["/"], 0.5);
+        histogram.observe(&["/"], 5.0);
+        summary.observe(&[], 1.0);
+
+        let encoder = prometheus::TextEncoder::new();
+        let text = encoder.encode_to_string(&registry.gather()).unwrap();
+        let families = super::text(&text).unwrap();
+
+        assert_eq!(families.len(), 4);
+        assert_eq!(encoder.encode_to_string(&families).unwrap(), text);
+    }
+
+    #[test]
+    fn test_openmetrics() {
+        let families = openmetrics(
+            "# HELP requests The total number of requests.\n\
+            # TYPE requests counter\n\
+            # UNIT requests requests\n\
+            requests_total{method=\"GET\"} 3.0 1700000000.5\n\
+            requests_created{method=\"GET\"} 1699999999.0\n\
+            # TYPE latency_seconds histogram\n\
+            latency_seconds_bucket{le=\"1.0\"} 1\n\
+            latency_seconds_bucket{le=\"+Inf\"} 2\n\
+            latency_seconds_sum 3.5\n\
+            # EOF\n\
+            ignored 1\n",
+        )
+        .unwrap();
+
+        assert_eq!(families.len(), 2);
+        assert_eq!(families[0].name(), "requests");
+        assert_eq!(families[0].get_field_type(), MetricType::COUNTER);
+        let metric = &families[0].get_metric()[0];
+        assert_eq!(metric.get_counter().value(), 3.0);
+        assert_eq!(metric.timestamp_ms(), 1_700_000_000_500);
+
+        let histogram = families[1].get_metric()[0].get_histogram();
+        assert_eq!(histogram.get_bucket().len(), 1);
+        assert_eq!(histogram.sample_count(), 2);
+        assert_eq!(histogram.sample_sum(), 3.5);
+    }
+
+    #[test]
+    fn test_openmetrics_timestamps() {
+        let families = openmetrics("# TYPE a gauge\na 1 1700000000\n# EOF\n").unwrap();
+        assert_eq!(families[0].get_metric()[0].timestamp_ms(), 1_700_000_000_000);
+
+        // The Prometheus text format has integer timestamps in milliseconds.
+        let families = text("# TYPE a gauge\na 1 1700000000\n").unwrap();
+        assert_eq!(families[0].get_metric()[0].timestamp_ms(), 1_700_000_000);
+        assert!(text("a 1 1700000000.5").unwrap_err().message.contains("Invalid timestamp"));
+    }
+
+    #[test]
+    fn test_openmetrics_types() {
+        let families = openmetrics(
+            "# TYPE build info\n\
+            build_info{version=\"1.0\"} 1\n\
+            # TYPE state stateset\n\
+            state{state=\"ready\"} 1\n\
+            state{state=\"failed\"} 0\n\
+            # TYPE other unknown\n\
+            other 2\n\
+            # EOF\n",
+        )
+        .unwrap();
+
+        assert_eq!(families.len(), 3);
+        assert_eq!(families[0].name(), "build_info");
+        assert_eq!(families[0].get_field_type(), MetricType::GAUGE);
+        assert_eq!(families[0].get_metric()[0].get_gauge().value(), 1.0);
+        assert_eq!(families[1].name(), "state");
+        assert_eq!(families[1].get_field_type(), MetricType::GAUGE);
+        assert_eq!(families[1].get_metric().len(), 2);
+        assert_eq!(families[2].get_field_type(), MetricType::UNTYPED);
+
+        let error = |input: &str| openmetrics(input).unwrap_err().message;
+        assert!(error("# TYPE a info\na 1").contains("Invalid sample"));
+        assert!(error("# TYPE a gaugehistogram").contains("Unsupported metric type"));
+        assert!(error("# TYPE a untyped").contains("Unsupported metric type"));
+    }
+
+    #[test]
+    fn test_untyped() {
+        let families = text("up 1 1700000000000\nup{job=\"a\"} 0\n").unwrap();
+
+        assert_eq!(families.len(), 1);
+        assert_eq!(families[0].get_field_type(), MetricType::UNTYPED);
+        assert_eq!(families[0].get_metric().len(), 2);
+        assert_eq!(families[0].get_metric()[0].timestamp_ms(), 1_700_000_000_000);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input: &str| text(input).unwrap_err();
+
+        assert_eq!(error("# TYPE a counter\na 1\n\na{b=\"c\" 1").line, 4);
+        assert!(error("a{b=\"c} 1").message.contains("Unterminated"));
+        assert!(error("1a 1").message.contains("Invalid metric name"));
+        assert!(error("a{b=\"1\",b=\"2\"} 1").message.contains("Duplicate label"));
+        assert!(error("a one").message.contains("Invalid value"));
+        assert!(error("a 1 2 3").message.contains("Unexpected"));
+        assert!(error("# TYPE a foo").message.contains("Unsupported metric type"));
+        assert!(error("# TYPE a gauge\n# TYPE a gauge").message.contains("Duplicate TYPE"));
+        assert!(error("# TYPE a histogram\na_bucket 1").message.contains("Missing `le` label"));
+        assert!(error("# TYPE a histogram\na 1").message.contains("Invalid sample"));
+    }
+}
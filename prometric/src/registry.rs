@@ -0,0 +1,472 @@
+//! Bookkeeping of the metrics registered with a [`prometheus::Registry`].
+//!
+//! A registry doesn't expose what has been registered with it, and registries can't be told apart
+//! from the outside. To detect conflicting definitions and reuse registered metrics, every
+//! registry that prometric registers metrics with gets a hidden [`StateCollector`], which has a
+//! unique const label and doesn't collect anything. The state of a registry is found by probing the
+//! registry with the known states: only the state that's already registered fails with
+//! [`prometheus::Error::AlreadyReg`]. Probes never register anything (see [`StateProbe`]), and the
+//! state found for a registry is cached by its address, so that usually a single probe is needed.
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    sync::{
+        Arc, LazyLock, Mutex, Weak,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use prometheus::{
+    core::{Collector, Desc},
+    proto::{self, MetricType},
+};
+
+use crate::Error;
+
+/// The name of the hidden state collector. It never shows up in the output, as it doesn't collect
+/// anything.
+const STATE_NAME: &str = "prometric_registry_state";
+
+/// The states of all registries that metrics have been registered with.
+static STATES: LazyLock<Mutex<States>> = LazyLock::new(Default::default);
+
+#[derive(Debug, Default)]
+struct States {
+    /// The states of all live registries.
+    all: Vec<Weak<RegistryState>>,
+    /// The state last found for a registry, by its address. Addresses don't identify registries
+    /// (a registry can be moved, its clones share its state, and a dropped registry's address can
+    /// be reused), so a cached state is verified with a probe before it's used.
+    by_address: HashMap<usize, Weak<RegistryState>>,
+}
+
+impl States {
+    /// Find the state of the registry among the known states.
+    fn find(&mut self, registry: &prometheus::Registry) -> Option<Arc<RegistryState>> {
+        let address = registry as *const prometheus::Registry as usize;
+        if let Some(state) = self.by_address.get(&address).and_then(Weak::upgrade)
+            && is_registered(&state, registry)
+        {
+            return Some(state);
+        }
+
+        self.all.retain(|state| state.strong_count() > 0);
+        self.by_address.retain(|_, state| state.strong_count() > 0);
+
+        let state = self
+            .all
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|state| is_registered(state, registry))?;
+        self.by_address.insert(address, Arc::downgrade(&state));
+
+        Some(state)
+    }
+
+    /// Record the state of a newly registered registry.
+    fn insert(&mut self, registry: &prometheus::Registry, state: &Arc<RegistryState>) {
+        let address = registry as *const prometheus::Registry as usize;
+        self.all.push(Arc::downgrade(state));
+        self.by_address.insert(address, Arc::downgrade(state));
+    }
+}
+
+/// The options of a metric besides its type, help and labels. Like those, they must be the same for
+/// every instance of a registered metric.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct MetricOptions {
+    pub(crate) unit: Option<String>,
+    /// The buckets of a histogram.
+    pub(crate) buckets: Option<Vec<f64>>,
+    /// The quantiles of a summary.
+    pub(crate) quantiles: Option<Vec<f64>>,
+    /// The maximum age of the observations of a summary.
+    pub(crate) max_age: Option<Duration>,
+    /// The sample rate of a histogram or summary.
+    pub(crate) sample: Option<f64>,
+}
+
+impl MetricOptions {
+    pub(crate) fn new(unit: Option<&str>) -> Self {
+        Self { unit: unit.map(ToOwned::to_owned), ..Default::default() }
+    }
+}
+
+/// The definition of a registered metric.
+#[derive(Debug, Clone, PartialEq)]
+struct Definition {
+    kind: MetricType,
+    help: String,
+    /// The sorted names of the variable and const labels.
+    labels: Vec<String>,
+    options: MetricOptions,
+}
+
+impl Definition {
+    /// Build the definition of a collector with a single descriptor.
+    fn new(kind: MetricType, options: MetricOptions, desc: &Desc) -> Self {
+        let mut labels = desc.variable_labels.clone();
+        labels.extend(desc.const_label_pairs.iter().map(|pair| pair.name().to_owned()));
+        labels.sort();
+
+        Self { kind, help: desc.help.clone(), labels, options }
+    }
+
+    /// The names of the series that are exposed for a metric with the given name.
+    fn series_names(&self, name: &str) -> Vec<String> {
+        match self.kind {
+            MetricType::HISTOGRAM => {
+                vec![format!("{name}_bucket"), format!("{name}_sum"), format!("{name}_count")]
+            }
+            MetricType::SUMMARY => {
+                vec![name.to_owned(), format!("{name}_sum"), format!("{name}_count")]
+            }
+            _ => vec![name.to_owned()],
+        }
+    }
+}
+
+impl fmt::Display for Definition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let MetricOptions { unit, buckets, quantiles, max_age, sample } = &self.options;
+
+        write!(f, "{}", format!("{:?}", self.kind).to_lowercase())?;
+        if let Some(unit) = unit {
+            write!(f, " in {unit}")?;
+        }
+        write!(f, " with labels [{}] and help {:?}", self.labels.join(", "), self.help)?;
+        if let Some(buckets) = buckets {
+            write!(f, ", buckets {buckets:?}")?;
+        }
+        if let Some(quantiles) = quantiles {
+            write!(f, ", quantiles {quantiles:?}")?;
+        }
+        if let Some(max_age) = max_age {
+            write!(f, ", max age {max_age:?}")?;
+        }
+        if let Some(sample) = sample {
+            write!(f, ", sample rate {sample}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The metrics of a single registry.
+#[derive(Debug)]
+struct RegistryState {
+    desc: Desc,
+    metrics: Mutex<Metrics>,
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    /// The definitions of the registered metrics by name.
+    definitions: HashMap<String, Definition>,
+    /// The registered collectors by descriptor ID, i.e. by name and const label values.
+    collectors: HashMap<u64, Box<dyn Any + Send + Sync>>,
+}
+
+impl Metrics {
+    /// Record the definition of the metric described by `desc`. Returns an error naming both
+    /// definitions if it conflicts with a metric that's already registered, i.e. if the same name
+    /// is registered with a different type, help, labels or options (e.g. histogram buckets), or if
+    /// any of the exposed series names collide (e.g. a counter `foo_count` and a histogram `foo`).
+    fn define(
+        &mut self,
+        kind: MetricType,
+        options: MetricOptions,
+        desc: &Desc,
+    ) -> Result<(), Error> {
+        let name = &desc.fq_name;
+        let definition = Definition::new(kind, options, desc);
+
+        if let Some(existing) = self.definitions.get(name) {
+            if *existing != definition {
+                return Err(Error::Conflict(format!(
+                    "Conflicting definitions for metric `{name}`: it is already registered as a \
+                    {existing}, and can't be registered as a {definition}"
+                )));
+            }
+
+            return Ok(());
+        }
+
+        let series = definition.series_names(name);
+        for (other, existing) in &self.definitions {
+            if let Some(collision) =
+                existing.series_names(other).into_iter().find(|s| series.contains(s))
+            {
+                return Err(Error::Conflict(format!(
+                    "Conflicting definitions for series `{collision}`: it is exposed by the \
+                    existing metric `{other}` ({existing}), and by the metric `{name}` \
+                    ({definition})"
+                )));
+            }
+        }
+
+        self.definitions.insert(name.clone(), definition);
+
+        Ok(())
+    }
+}
+
+/// Wrapper to register a [`RegistryState`] with a [`prometheus::Registry`].
+#[derive(Debug, Clone)]
+struct StateCollector(Arc<RegistryState>);
+
+impl Collector for StateCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.0.desc]
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        Vec::new()
+    }
+}
+
+/// A collector that checks whether a state is registered with a registry, without registering
+/// anything.
+///
+/// It describes the state twice. A registry checks each descriptor in turn before registering a
+/// collector: if the state is registered, the first descriptor fails with
+/// [`prometheus::Error::AlreadyReg`], and otherwise the duplicate fails the registration. Either
+/// way the registered collectors are left untouched, so concurrent gathers and registrations never
+/// see the probe.
+#[derive(Debug)]
+struct StateProbe(Arc<RegistryState>);
+
+impl Collector for StateProbe {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.0.desc, &self.0.desc]
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        Vec::new()
+    }
+}
+
+/// Returns true if the state is registered with the registry, without registering it.
+fn is_registered(state: &Arc<RegistryState>, registry: &prometheus::Registry) -> bool {
+    let probe = StateProbe(state.clone());
+    matches!(registry.register(Box::new(probe)), Err(prometheus::Error::AlreadyReg))
+}
+
+/// Returns the state of the registry, registering a new one if it doesn't have one yet.
+fn state(registry: &prometheus::Registry) -> Arc<RegistryState> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+    // NOTE: The lock is held until a new state is registered, so that no other thread registers
+    // another state for the same registry in between.
+    let mut states = STATES.lock().unwrap();
+    if let Some(state) = states.find(registry) {
+        return state;
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let desc = Desc::new(
+        STATE_NAME.to_owned(),
+        "The state of the registry, used by prometric.".to_owned(),
+        Vec::new(),
+        HashMap::from([("id".to_owned(), id.to_string())]),
+    )
+    .unwrap();
+
+    let state = Arc::new(RegistryState { desc, metrics: Mutex::default() });
+    registry
+        .register(Box::new(StateCollector(state.clone())))
+        .expect("Failed to register the registry state");
+    states.insert(registry, &state);
+
+    state
+}
+
+/// Returns the units of the metrics registered with the registry, by metric name.
+#[cfg_attr(not(feature = "exporter"), allow(dead_code))]
+pub(crate) fn units(registry: &prometheus::Registry) -> HashMap<String, String> {
+    let Some(state) = STATES.lock().unwrap().find(registry) else {
+        return HashMap::new();
+    };
+
+    let metrics = state.metrics.lock().unwrap();
+    metrics
+        .definitions
+        .iter()
+        .filter_map(|(name, definition)| Some((name.clone(), definition.options.unit.clone()?)))
+        .collect()
+}
+
+/// Register the collector of the given kind and options with the registry, and return the collector
+/// to use. If a collector with the same descriptors is already registered, it's returned instead.
+///
+/// The first descriptor of the collector describes the metric. Any further descriptors describe
+/// the companion counters that are collected with it, e.g. the observations of sampled metrics.
+pub(crate) fn register<C>(
+    registry: &prometheus::Registry,
+    collector: C,
+    kind: MetricType,
+    options: MetricOptions,
+) -> Result<C, Error>
+where
+    C: Collector + Clone + Send + Sync + 'static,
+{
+    let descs = collector.desc().into_iter().cloned().collect::<Vec<_>>();
+    let desc = &descs[0];
+    let name = &desc.fq_name;
+
+    let state = state(registry);
+    let mut metrics = state.metrics.lock().unwrap();
+    metrics.define(kind, options, desc)?;
+    for companion in &descs[1..] {
+        metrics.define(MetricType::COUNTER, MetricOptions::default(), companion)?;
+    }
+
+    match registry.register(Box::new(collector.clone())) {
+        Ok(()) => {}
+        Err(prometheus::Error::AlreadyReg) => {
+            return metrics
+                .collectors
+                .get(&desc.id)
+                .and_then(|existing| existing.downcast_ref::<C>())
+                .cloned()
+                .ok_or_else(|| Error::AlreadyRegistered(name.clone()));
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    metrics.collectors.insert(desc.id, Box::new(collector.clone()));
+
+    Ok(collector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc(name: &str, help: &str, labels: &[&str]) -> Desc {
+        let labels = labels.iter().map(|l| l.to_string()).collect();
+        Desc::new(name.to_owned(), help.to_owned(), labels, HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn test_define() {
+        let mut metrics = Metrics::default();
+
+        let counter = desc("requests", "Requests", &["method"]);
+        metrics.define(MetricType::COUNTER, MetricOptions::default(), &counter).unwrap();
+        // Identical definitions are fine.
+        metrics.define(MetricType::COUNTER, MetricOptions::default(), &counter).unwrap();
+
+        // Different types, help strings or labels conflict.
+        assert!(metrics.define(MetricType::GAUGE, MetricOptions::default(), &counter).is_err());
+        let help = desc("requests", "Other", &["method"]);
+        assert!(metrics.define(MetricType::COUNTER, MetricOptions::default(), &help).is_err());
+        let labels = desc("requests", "Requests", &["path"]);
+        assert!(metrics.define(MetricType::COUNTER, MetricOptions::default(), &labels).is_err());
+        assert!(
+            metrics
+                .define(MetricType::COUNTER, MetricOptions::new(Some("seconds")), &counter)
+                .is_err()
+        );
+
+        // Series names of histograms collide with other metrics.
+        let count = desc("duration_count", "Count", &[]);
+        metrics.define(MetricType::COUNTER, MetricOptions::default(), &count).unwrap();
+        let histogram = desc("duration", "Duration", &[]);
+        let err = metrics
+            .define(MetricType::HISTOGRAM, MetricOptions::default(), &histogram)
+            .unwrap_err();
+        assert!(err.to_string().contains("duration_count"), "{err}");
+
+        // Histograms with different buckets conflict.
+        let latency = desc("latency", "Latency", &[]);
+        let buckets = |buckets: &[f64]| MetricOptions {
+            buckets: Some(buckets.to_vec()),
+            ..Default::default()
+        };
+        metrics.define(MetricType::HISTOGRAM, buckets(&[1.0, 2.0]), &latency).unwrap();
+        metrics.define(MetricType::HISTOGRAM, buckets(&[1.0, 2.0]), &latency).unwrap();
+        let err =
+            metrics.define(MetricType::HISTOGRAM, buckets(&[1.0, 5.0]), &latency).unwrap_err();
+        assert!(matches!(err, Error::Conflict(_)));
+        assert!(err.to_string().contains("buckets [1.0, 5.0]"), "{err}");
+    }
+
+    #[test]
+    fn test_state() {
+        let registry = prometheus::Registry::new();
+        let other = prometheus::Registry::new();
+
+        let first = state(&registry);
+        assert!(Arc::ptr_eq(&state(&registry), &first));
+        assert!(!Arc::ptr_eq(&state(&other), &first));
+
+        // Clones share the registry, so they have the same state, although their address differs.
+        let clone = Box::new(registry.clone());
+        assert!(Arc::ptr_eq(&state(&clone), &first));
+
+        // Probing a registry doesn't register the state with it.
+        let unrelated = prometheus::Registry::new();
+        assert!(!is_registered(&first, &unrelated));
+        unrelated.register(Box::new(StateCollector(first.clone()))).unwrap();
+        assert!(is_registered(&first, &unrelated));
+    }
+
+    #[test]
+    fn test_register() {
+        let registry = prometheus::Registry::new();
+        let other = prometheus::Registry::new();
+
+        let counter = || {
+            let opts = prometheus::Opts::new("requests", "Requests");
+            prometheus::IntCounterVec::new(opts, &["method"]).unwrap()
+        };
+
+        let first =
+            register(&registry, counter(), MetricType::COUNTER, MetricOptions::default()).unwrap();
+        first.with_label_values(&["GET"]).inc();
+
+        // Registering again returns the registered collector.
+        let reused =
+            register(&registry, counter(), MetricType::COUNTER, MetricOptions::default()).unwrap();
+        assert_eq!(reused.with_label_values(&["GET"]).get(), 1);
+
+        // Collectors of another type can't be reused.
+        let other_type =
+            prometheus::CounterVec::new(prometheus::Opts::new("requests", "Requests"), &["method"])
+                .unwrap();
+        let err = register(&registry, other_type, MetricType::COUNTER, MetricOptions::default());
+        assert!(matches!(err, Err(Error::AlreadyRegistered(_))));
+
+        // Registries are independent.
+        let err = register(
+            &other,
+            prometheus::IntGaugeVec::new(
+                prometheus::Opts::new("requests", "Requests"),
+                &["method"],
+            )
+            .unwrap(),
+            MetricType::GAUGE,
+            MetricOptions::default(),
+        );
+        assert!(err.is_ok());
+
+        // Units are tracked per registry.
+        let duration = prometheus::Gauge::new("duration_seconds", "Duration").unwrap();
+        register(&registry, duration, MetricType::GAUGE, MetricOptions::new(Some("seconds")))
+            .unwrap();
+        assert_eq!(
+            units(&registry),
+            HashMap::from([("duration_seconds".into(), "seconds".into())])
+        );
+        assert!(units(&other).is_empty());
+        assert!(units(&prometheus::Registry::new()).is_empty());
+
+        // The state collector doesn't show up in the output.
+        let output = prometheus::TextEncoder::new().encode_to_string(&registry.gather()).unwrap();
+        assert!(!output.contains(STATE_NAME));
+    }
+}
@@ -0,0 +1,116 @@
+//! Sampling support for histograms and summaries.
+
+use std::{
+    cell::Cell,
+    hash::{BuildHasher as _, RandomState},
+};
+
+use prometheus::{
+    core::{AtomicU64, Collector as _, Desc, GenericCounter, GenericCounterVec},
+    proto,
+};
+
+use crate::{Error, RemoveSeries};
+
+/// Decides which observations are recorded, and counts all observations (sampled or not) in a
+/// companion `<name>_observations_total` counter so that totals stay correct.
+///
+/// The companion counter isn't registered on its own: it's described and collected together with
+/// the metric, so that both are always gathered (and their series expired) at the same time.
+#[derive(Debug, Clone)]
+pub(crate) struct Sampler {
+    rate: f64,
+    observations: GenericCounterVec<AtomicU64>,
+}
+
+impl Sampler {
+    /// Create a new sampler for the metric with the given name.
+    ///
+    /// Returns an error if the rate is not in the interval `(0, 1]`.
+    pub(crate) fn new(name: &str, labels: &[&str], rate: f64) -> Result<Self, Error> {
+        if !(rate > 0.0 && rate <= 1.0) {
+            return Err(Error::InvalidOptions(format!(
+                "Invalid sample rate {rate} for metric {name}: must be in (0, 1]"
+            )));
+        }
+
+        let name = format!("{name}_observations_total");
+        let help = format!(
+            "The total number of observations, including those dropped by sampling. Sample rate: {rate}."
+        );
+        let opts = prometheus::Opts::new(&name, help);
+        let observations = GenericCounterVec::<AtomicU64>::new(opts, labels)?;
+
+        Ok(Self { rate, observations })
+    }
+
+    /// The descriptor of the companion counter.
+    pub(crate) fn desc(&self) -> &Desc {
+        self.observations.desc()[0]
+    }
+
+    /// Collect the companion counter.
+    pub(crate) fn collect(&self) -> Vec<proto::MetricFamily> {
+        self.observations.collect()
+    }
+
+    /// Remove the series of the companion counter with the given label values.
+    pub(crate) fn remove(&self, values: &[&str]) {
+        self.observations.remove_series(values);
+    }
+
+    /// Resolve the companion counter for the given label values.
+    pub(crate) fn bind(&self, labels: &[&str]) -> BoundSampler {
+        BoundSampler { rate: self.rate, observations: self.observations.with_label_values(labels) }
+    }
+}
+
+/// A [`Sampler`] for a single label combination.
+#[derive(Debug, Clone)]
+pub(crate) struct BoundSampler {
+    rate: f64,
+    observations: GenericCounter<AtomicU64>,
+}
+
+impl BoundSampler {
+    /// Count the observation, and return whether it should be recorded.
+    pub(crate) fn sample(&self) -> bool {
+        self.observations.inc();
+        self.rate >= 1.0 || random() < self.rate
+    }
+}
+
+/// Returns a pseudo-random number in `[0, 1)` from a thread-local xorshift generator. This is not
+/// suitable for anything but sampling.
+fn random() -> f64 {
+    thread_local! {
+        static STATE: Cell<u64> =
+            Cell::new(RandomState::new().hash_one(std::thread::current().id()) | 1);
+    }
+
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_rate() {
+        let sampler = Sampler::new("test_sampled", &[], 0.1).unwrap();
+
+        let sampler = sampler.bind(&[]);
+        let sampled = (0..100_000).filter(|_| sampler.sample()).count();
+        assert!((9_000..11_000).contains(&sampled), "sampled {sampled} observations");
+
+        assert_eq!(sampler.observations.get(), 100_000);
+    }
+}
@@ -0,0 +1,634 @@
+//! Tracking of the label series of a metric, to limit their number and expire idle series.
+//!
+//! The state is shared by all instances of a metric: it's registered together with the metric
+//! vectors of the instances in a [`Tracked`] collector, so that instances of a registered metric
+//! also share its state.
+
+use std::{
+    collections::{HashMap, hash_map},
+    iter,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use prometheus::{
+    IntCounter, IntCounterVec,
+    core::{Collector, Desc, MetricVec, MetricVecBuilder},
+    proto::{self, MetricType},
+};
+
+use crate::{
+    ConflictPolicy, Error, MetricOptions, Sampler, StaticLabels,
+    label::{SeriesKey, Values, with_collected},
+    registry,
+};
+
+/// The default label value that new label combinations are folded into once the cardinality limit
+/// of a metric is reached.
+pub const DEFAULT_OVERFLOW_VALUE: &str = "__overflow__";
+
+/// The name of the counter of cardinality limit hits, which is shared by all metrics of a registry.
+const LIMIT_HITS_NAME: &str = "prometric_cardinality_limit_hits_total";
+
+/// Options for the label series of a metric.
+#[derive(Debug, Clone, Default)]
+pub struct SeriesOptions {
+    max_cardinality: Option<usize>,
+    overflow_value: Option<String>,
+    ttl: Option<Duration>,
+}
+
+impl SeriesOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the number of label combinations of the metric. Once the limit is reached, new label
+    /// combinations are folded into the overflow value, and the
+    /// `prometric_cardinality_limit_hits_total` counter is incremented.
+    pub fn max_cardinality(mut self, max: usize) -> Self {
+        self.max_cardinality = Some(max);
+        self
+    }
+
+    /// Set the label value that new label combinations are folded into once the cardinality limit
+    /// is reached. Defaults to [`DEFAULT_OVERFLOW_VALUE`].
+    pub fn overflow_value(mut self, value: impl Into<String>) -> Self {
+        self.overflow_value = Some(value.into());
+        self
+    }
+
+    /// Remove series that haven't been updated within the TTL. Expired series are removed when the
+    /// metric is collected, and are created again on their next update.
+    ///
+    /// Only updates through the metric (or its accessors) refresh a series. Handles returned by
+    /// `bind` refresh it when they're created, and keep updating the removed series after it
+    /// expired.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+}
+
+/// The state of the label series of a metric.
+#[derive(Debug)]
+pub(crate) struct Series {
+    limit: Option<CardinalityLimit>,
+    ttl: Option<Duration>,
+    /// The instant that the update times of the series are relative to.
+    epoch: Instant,
+    /// The series of the metric. Only tracked if there's a cardinality limit or a TTL. Updates of
+    /// existing series only take the read lock.
+    set: Option<RwLock<SeriesSet>>,
+}
+
+#[derive(Debug)]
+struct CardinalityLimit {
+    max: usize,
+    overflow_value: String,
+    /// The hits counter for this metric.
+    hits: IntCounter,
+}
+
+#[derive(Debug, Default)]
+struct SeriesSet {
+    /// The series by their label values.
+    entries: HashMap<SeriesKey, Entry>,
+    /// The number of overflow series, which don't count towards the cardinality limit.
+    overflow: usize,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// The time of the last update, in nanoseconds since the epoch of the series.
+    last_update: AtomicU64,
+    overflow: bool,
+}
+
+impl Series {
+    pub(crate) fn new(
+        registry: &prometheus::Registry,
+        name: &str,
+        options: SeriesOptions,
+    ) -> Result<Self, Error> {
+        let limit = options
+            .max_cardinality
+            .map(|max| {
+                if max == 0 {
+                    return Err(Error::InvalidOptions(format!(
+                        "Invalid cardinality limit for metric {name}: must be positive"
+                    )));
+                }
+
+                let opts = prometheus::Opts::new(
+                    LIMIT_HITS_NAME,
+                    "The number of new label combinations that were folded into the overflow \
+                    value, because the cardinality limit of the metric was reached.",
+                );
+                let hits = IntCounterVec::new(opts, &["metric"])?;
+                let hits =
+                    registry::register(registry, hits, MetricType::COUNTER, Default::default())?;
+
+                Ok(CardinalityLimit {
+                    max,
+                    overflow_value: options
+                        .overflow_value
+                        .unwrap_or_else(|| DEFAULT_OVERFLOW_VALUE.to_owned()),
+                    hits: hits.with_label_values(&[name]),
+                })
+            })
+            .transpose()?;
+
+        if options.ttl.is_some_and(|ttl| ttl.is_zero()) {
+            return Err(Error::InvalidOptions(format!(
+                "Invalid TTL for metric {name}: must be non-zero"
+            )));
+        }
+
+        let set = (limit.is_some() || options.ttl.is_some()).then(RwLock::default);
+        Ok(Self { limit, ttl: options.ttl, epoch: Instant::now(), set })
+    }
+
+    /// The time since the epoch of the series, in nanoseconds.
+    fn nanos_since_epoch(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.epoch).as_nanos() as u64
+    }
+
+    /// Call the closure with the values of all variable labels of the series to update: the static
+    /// label values followed by `labels`. If the series is new and the cardinality limit has been
+    /// reached, `labels` are replaced by the overflow value.
+    #[inline]
+    pub(crate) fn with_values<R>(
+        &self,
+        static_labels: &StaticLabels,
+        labels: &[&str],
+        f: impl FnOnce(&[&str]) -> R,
+    ) -> R {
+        static_labels.with_values(labels, |values| {
+            let Some(set) = &self.set else {
+                return f(values);
+            };
+
+            let now = self.nanos_since_epoch(Instant::now());
+            // NOTE: The lock is held while the series is resolved, so that it can't expire in
+            // between.
+            {
+                let set = set.read().unwrap();
+                if set.touch(values, now) {
+                    return f(values);
+                }
+            }
+
+            let mut set = set.write().unwrap();
+            // The series may have been added while the lock was released.
+            if set.touch(values, now) {
+                return f(values);
+            }
+
+            match &self.limit {
+                Some(limit) if set.entries.len() - set.overflow >= limit.max => {
+                    limit.hits.inc();
+
+                    let static_values = &values[..values.len() - labels.len()];
+                    let overflow = iter::repeat_n(limit.overflow_value.as_str(), labels.len());
+                    let values = static_values.iter().copied().chain(overflow);
+
+                    with_collected(values, static_values.len() + labels.len(), |values| {
+                        if !set.touch(values, now) {
+                            set.insert(values, now, true);
+                        }
+
+                        f(values)
+                    })
+                }
+                _ => {
+                    set.insert(values, now, false);
+                    f(values)
+                }
+            }
+        })
+    }
+
+    /// Remove the series with the given label values from the collector and the companion counter,
+    /// and stop tracking it.
+    pub(crate) fn remove(
+        &self,
+        collector: &impl RemoveSeries,
+        sampler: Option<&Sampler>,
+        values: &[&str],
+    ) {
+        if let Some(set) = &self.set {
+            set.write().unwrap().remove(values);
+        }
+
+        collector.remove_series(values);
+        if let Some(sampler) = sampler {
+            sampler.remove(values);
+        }
+    }
+
+    /// Stop tracking the series with the given static label values, e.g. because their instance has
+    /// been replaced.
+    pub(crate) fn forget(&self, static_labels: &StaticLabels) {
+        let Some(set) = &self.set else {
+            return;
+        };
+
+        let mut set = set.write().unwrap();
+        let SeriesSet { entries, overflow } = &mut *set;
+        entries.retain(|key, entry| {
+            if !static_labels.is_prefix_of(key.values()) {
+                return true;
+            }
+
+            if entry.overflow {
+                *overflow -= 1;
+            }
+
+            false
+        });
+    }
+
+    /// Remove all series of the collector with the given static label values.
+    pub(crate) fn clear(
+        &self,
+        collector: &impl RemoveSeries,
+        sampler: Option<&Sampler>,
+        static_labels: &StaticLabels,
+    ) {
+        for values in collector.series_values() {
+            if static_labels.is_prefix_of(&values) {
+                self.remove(
+                    collector,
+                    sampler,
+                    &values.iter().map(String::as_str).collect::<Vec<_>>(),
+                );
+            }
+        }
+    }
+
+    /// Remove the series that haven't been updated within the TTL, calling `remove` with the label
+    /// values of each.
+    pub(crate) fn expire(&self, remove: impl FnMut(&[&str])) {
+        self.expire_at(Instant::now(), remove);
+    }
+
+    fn expire_at(&self, now: Instant, mut remove: impl FnMut(&[&str])) {
+        let (Some(ttl), Some(set)) = (self.ttl, &self.set) else {
+            return;
+        };
+
+        let now = self.nanos_since_epoch(now);
+        let ttl = ttl.as_nanos() as u64;
+
+        let mut set = set.write().unwrap();
+        let SeriesSet { entries, overflow } = &mut *set;
+        entries.retain(|key, entry| {
+            if now.saturating_sub(entry.last_update.load(Ordering::Relaxed)) < ttl {
+                return true;
+            }
+
+            remove(&key.values().iter().map(String::as_str).collect::<Vec<_>>());
+            if entry.overflow {
+                *overflow -= 1;
+            }
+
+            false
+        });
+    }
+}
+
+impl SeriesSet {
+    /// Mark the series as updated at `now`. Returns false if it isn't tracked.
+    fn touch(&self, values: &[&str], now: u64) -> bool {
+        let entry = self.entries.get(&values as &dyn Values);
+        entry.map(|entry| entry.last_update.fetch_max(now, Ordering::Relaxed)).is_some()
+    }
+
+    fn insert(&mut self, values: &[&str], now: u64, overflow: bool) {
+        let entry = Entry { last_update: AtomicU64::new(now), overflow };
+        self.entries.insert(SeriesKey::new(values), entry);
+        if overflow {
+            self.overflow += 1;
+        }
+    }
+
+    fn remove(&mut self, values: &[&str]) {
+        if self.entries.remove(&values as &dyn Values).is_some_and(|entry| entry.overflow) {
+            self.overflow -= 1;
+        }
+    }
+}
+
+/// A metric vector whose series can be listed and removed by their label values.
+pub(crate) trait RemoveSeries {
+    /// Remove the series with the given label values, if it exists.
+    fn remove_series(&self, values: &[&str]);
+
+    /// Returns the label values of all series, in the order of the variable labels.
+    fn series_values(&self) -> Vec<Vec<String>>;
+}
+
+impl<T: MetricVecBuilder> RemoveSeries for MetricVec<T> {
+    fn remove_series(&self, values: &[&str]) {
+        // NOTE: This only fails if the series doesn't exist.
+        let _ = self.remove_label_values(values);
+    }
+
+    fn series_values(&self) -> Vec<Vec<String>> {
+        let names = &self.desc()[0].variable_labels;
+
+        self.collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .map(|metric| {
+                names
+                    .iter()
+                    .map(|name| {
+                        let pair = metric.get_label().iter().find(|pair| pair.name() == name);
+                        pair.map(|pair| pair.value().to_owned()).unwrap_or_default()
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// A metric vector whose series can be looked up without creating them.
+pub(crate) trait FindSeries {
+    /// Returns the collected series with the given label values, if it exists. This collects and
+    /// scans every series of the vector, so it's O(series).
+    ///
+    /// # Panics
+    /// Panics if the number of label values doesn't match the number of variable labels, like
+    /// `with_label_values`.
+    fn find_series(&self, values: &[&str]) -> Option<proto::Metric>;
+}
+
+impl<T: MetricVecBuilder> FindSeries for MetricVec<T> {
+    fn find_series(&self, values: &[&str]) -> Option<proto::Metric> {
+        let names = &self.desc()[0].variable_labels;
+        assert_eq!(
+            values.len(),
+            names.len(),
+            "Inconsistent label cardinality: expected {} label values, got {}",
+            names.len(),
+            values.len()
+        );
+
+        // NOTE: `get_metric_with_label_values` creates missing series, so the collected series
+        // are searched instead.
+        self.collect().into_iter().flat_map(|mut family| family.take_metric()).find(|metric| {
+            names.iter().zip(values).all(|(name, value)| {
+                metric.get_label().iter().any(|pair| pair.name() == name && pair.value() == *value)
+            })
+        })
+    }
+}
+
+/// A collector together with the series state of the metric, which is registered in its place.
+///
+/// Every instance of the metric (i.e. every set of static label values) has its own collector,
+/// which is what the conflict policy applies to: an instance with the same static label values as
+/// an existing one replaces it, reuses it or fails, while instances with other static label values
+/// are added. The companion counter of sampled metrics is described and collected with the metric,
+/// and its series are removed together with the series of the metric.
+#[derive(Debug, Clone)]
+pub(crate) struct Tracked<C> {
+    /// The collector of the instance. The first instance also describes the registered metric.
+    pub(crate) collector: C,
+    pub(crate) sampler: Option<Sampler>,
+    pub(crate) series: Arc<Series>,
+    /// The instances of the registered metric, by their static label values.
+    instances: Arc<RwLock<Instances<C>>>,
+}
+
+type Instances<C> = HashMap<Arc<[String]>, Instance<C>>;
+
+/// The collectors of an instance of a metric.
+#[derive(Debug, Clone)]
+struct Instance<C> {
+    collector: C,
+    sampler: Option<Sampler>,
+}
+
+impl<C> Tracked<C>
+where
+    C: Collector + RemoveSeries + Clone + Send + Sync + 'static,
+{
+    pub(crate) fn new(collector: C, sampler: Option<Sampler>, series: Series) -> Self {
+        Self { collector, sampler, series: Arc::new(series), instances: Arc::default() }
+    }
+
+    /// Register the metric of the given kind and options with the registry (or find the registered
+    /// metric), and add the collector as the instance with the given static label values. If the
+    /// metric already has an instance with the same static label values, the conflict policy
+    /// decides whether it's replaced, reused or an error is returned.
+    ///
+    /// Returns the collectors of the instance to use, together with the shared series state.
+    pub(crate) fn register(
+        self,
+        registry: &prometheus::Registry,
+        kind: MetricType,
+        options: MetricOptions,
+        static_labels: &StaticLabels,
+        on_conflict: ConflictPolicy,
+    ) -> Result<Self, Error> {
+        let instance =
+            Instance { collector: self.collector.clone(), sampler: self.sampler.clone() };
+        let name = self.collector.desc()[0].fq_name.clone();
+        let registered = registry::register(registry, self, kind, options)?;
+
+        let mut instances = registered.instances.write().unwrap();
+        let instance = match instances.entry(static_labels.values().clone()) {
+            hash_map::Entry::Vacant(entry) => entry.insert(instance).clone(),
+            hash_map::Entry::Occupied(mut entry) => match on_conflict {
+                ConflictPolicy::Error => return Err(Error::AlreadyRegistered(name)),
+                ConflictPolicy::Replace => {
+                    registered.series.forget(static_labels);
+                    entry.insert(instance.clone());
+                    instance
+                }
+                ConflictPolicy::Reuse => entry.get().clone(),
+            },
+        };
+        drop(instances);
+
+        Ok(Self {
+            collector: instance.collector,
+            sampler: instance.sampler,
+            series: registered.series,
+            instances: registered.instances,
+        })
+    }
+}
+
+impl<C: Collector + RemoveSeries> Collector for Tracked<C> {
+    fn desc(&self) -> Vec<&Desc> {
+        let mut desc = self.collector.desc();
+        desc.extend(self.sampler.as_ref().map(Sampler::desc));
+        desc
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        let instances = self.instances.read().unwrap();
+
+        self.series.expire(|values| {
+            let instance = instances.iter().find(|(static_values, _)| {
+                static_values.iter().zip(values).all(|(static_value, value)| static_value == value)
+            });
+
+            if let Some((_, Instance { collector, sampler })) = instance {
+                collector.remove_series(values);
+                if let Some(sampler) = sampler {
+                    sampler.remove(values);
+                }
+            }
+        });
+
+        let mut families: Vec<proto::MetricFamily> = Vec::new();
+        for Instance { collector, sampler } in instances.values() {
+            let collected =
+                collector.collect().into_iter().chain(sampler.iter().flat_map(Sampler::collect));
+
+            // Merge the families of the instances, so that every metric has a single family.
+            for mut family in collected {
+                match families.iter_mut().find(|existing| existing.name() == family.name()) {
+                    Some(existing) => existing.mut_metric().extend(family.take_metric()),
+                    None => families.push(family),
+                }
+            }
+        }
+
+        families
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resolve the label values of the series to update.
+    fn resolve(series: &Series, static_labels: &StaticLabels, labels: &[&str]) -> Vec<String> {
+        series.with_values(static_labels, labels, |values| {
+            values.iter().map(|v| v.to_string()).collect()
+        })
+    }
+
+    #[test]
+    fn test_cardinality_limit() {
+        let registry = prometheus::Registry::new();
+        let options = SeriesOptions::new().max_cardinality(2).overflow_value("other");
+        let series = Series::new(&registry, "requests", options).unwrap();
+        let static_labels =
+            StaticLabels::new([("shard".to_owned(), "0".to_owned())].into_iter().collect());
+
+        assert_eq!(resolve(&series, &static_labels, &["GET", "/a"]), ["0", "GET", "/a"]);
+        assert_eq!(resolve(&series, &static_labels, &["GET", "/b"]), ["0", "GET", "/b"]);
+        assert_eq!(resolve(&series, &static_labels, &["GET", "/c"]), ["0", "other", "other"]);
+        // Existing series are still updated.
+        assert_eq!(resolve(&series, &static_labels, &["GET", "/a"]), ["0", "GET", "/a"]);
+
+        let limit = series.limit.as_ref().unwrap();
+        assert_eq!(limit.hits.get(), 1);
+    }
+
+    #[test]
+    fn test_find_series() {
+        let opts = prometheus::Opts::new("requests", "Requests");
+        let counter = IntCounterVec::new(opts, &["method", "path"]).unwrap();
+        counter.with_label_values(&["GET", "/a"]).inc_by(2);
+
+        let metric = counter.find_series(&["GET", "/a"]).unwrap();
+        assert_eq!(metric.get_counter().value(), 2.0);
+        assert!(counter.find_series(&["GET", "/b"]).is_none());
+        // Looking up doesn't create the series.
+        assert_eq!(counter.series_values().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Inconsistent label cardinality")]
+    fn test_find_series_cardinality() {
+        let opts = prometheus::Opts::new("requests", "Requests");
+        let counter = IntCounterVec::new(opts, &["method", "path"]).unwrap();
+        counter.with_label_values(&["GET", "/a"]).inc();
+
+        let _ = counter.find_series(&["GET"]);
+    }
+
+    #[test]
+    fn test_series_keys() {
+        let registry = prometheus::Registry::new();
+        let options = SeriesOptions::new().max_cardinality(3);
+        let series = Series::new(&registry, "keys", options).unwrap();
+        let static_labels = StaticLabels::default();
+
+        // Series are told apart by their label values, not by a hash of them.
+        resolve(&series, &static_labels, &["ab", ""]);
+        resolve(&series, &static_labels, &["a", "b"]);
+        resolve(&series, &static_labels, &["a", "b"]);
+
+        let set = series.set.as_ref().unwrap().read().unwrap();
+        assert_eq!(set.entries.len(), 2);
+        assert!(set.entries.contains_key(&&["a", "b"][..] as &dyn Values));
+        assert!(!set.entries.contains_key(&&["a", ""][..] as &dyn Values));
+    }
+
+    #[test]
+    fn test_ttl() {
+        let registry = prometheus::Registry::new();
+        let options = SeriesOptions::new().max_cardinality(1).ttl(Duration::from_secs(60));
+        let series = Series::new(&registry, "peers", options).unwrap();
+        let static_labels = StaticLabels::default();
+
+        resolve(&series, &static_labels, &["a"]);
+        resolve(&series, &static_labels, &["b"]);
+
+        let mut removed = Vec::new();
+        let now = Instant::now();
+        series.expire_at(now, |values| removed.push(values.join(",")));
+        assert!(removed.is_empty());
+
+        series.expire_at(now + Duration::from_secs(61), |values| removed.push(values.join(",")));
+        removed.sort();
+        assert_eq!(removed, ["__overflow__", "a"]);
+
+        // Expired series no longer count towards the limit.
+        assert_eq!(resolve(&series, &static_labels, &["b"]), ["b"]);
+    }
+
+    #[test]
+    fn test_clear() {
+        let registry = prometheus::Registry::new();
+        let options = SeriesOptions::new().max_cardinality(2);
+        let series = Series::new(&registry, "requests", options).unwrap();
+        let shard = |value: &str| {
+            StaticLabels::new([("shard".to_owned(), value.to_owned())].into_iter().collect())
+        };
+        let (first, second) = (shard("0"), shard("1"));
+
+        let opts = prometheus::Opts::new("requests", "Requests");
+        let vec = IntCounterVec::new(opts, &["shard", "method"]).unwrap();
+        let inc = |static_labels: &StaticLabels, labels: &[&str]| {
+            series.with_values(static_labels, labels, |values| vec.with_label_values(values).inc())
+        };
+
+        inc(&first, &["GET"]);
+        inc(&second, &["GET"]);
+        series.remove(&vec, None, &["1", "GET"]);
+        assert_eq!(vec.series_values(), [["0", "GET"]]);
+
+        // Only the series with the static label values are cleared.
+        inc(&second, &["POST"]);
+        series.clear(&vec, None, &first);
+        assert_eq!(vec.series_values(), [["1", "POST"]]);
+
+        // Removed series no longer count towards the limit.
+        inc(&first, &["PUT"]);
+        assert_eq!(vec.series_values().len(), 2);
+        assert_eq!(series.limit.as_ref().unwrap().hits.get(), 0);
+    }
+}
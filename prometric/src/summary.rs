@@ -0,0 +1,619 @@
+//! A summary metric backed by a streaming quantile estimator over a sliding time window.
+//!
+//! The estimator is an implementation of the targeted quantiles algorithm described in
+//! "Effective Computation of Biased Quantiles over Data Streams" (Cormode et al.), which is the
+//! same approach used by the official Go client. Observations are inserted into a ring of
+//! `AGE_BUCKETS` streams, which are reset in turn so that the oldest stream always covers
+//! (roughly) the last `max_age` of observations.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use prometheus::{
+    core::{Collector, Desc},
+    proto,
+};
+
+use crate::{
+    ConflictPolicy, Error, MetricOptions, RemoveSeries, Sampler, Series, SeriesOptions,
+    StaticLabels, SummaryHandle, Tracked,
+    label::{SeriesKey, Values},
+};
+
+/// The default quantiles to report if none are provided.
+pub const DEFAULT_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// The default duration for which observations are kept in the sliding window.
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// The number of streams the sliding window is split into.
+const AGE_BUCKETS: u32 = 5;
+
+/// The number of observations that are buffered before being merged into a stream.
+const BUFFER_CAP: usize = 500;
+
+/// A summary metric, which reports the configured quantiles of the observations made over a
+/// sliding time window, together with the total count and sum of all observations.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    inner: Arc<SummaryVec>,
+    series: Arc<Series>,
+    static_labels: StaticLabels,
+    sampler: Option<Sampler>,
+}
+
+impl Summary {
+    /// Create a new summary metric with the given registry, name, help, labels, static labels,
+    /// quantiles, maximum age of observations and sample rate.
+    ///
+    /// If a sample rate is provided, only that fraction of observations is recorded, and the total
+    /// number of observations is tracked in a companion `<name>_observations_total` counter.
+    ///
+    /// # Panics
+    /// Panics if any of the quantiles is not in the open interval `(0, 1)`, if `max_age` is zero,
+    /// or if the metric can't be registered. See [`Summary::try_new`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<Q: Into<Vec<f64>>>(
+        registry: &prometheus::Registry,
+        name: &str,
+        help: &str,
+        labels: &[&str],
+        static_labels: HashMap<String, String>,
+        quantiles: Option<Q>,
+        max_age: Option<Duration>,
+        sample: Option<f64>,
+    ) -> Self {
+        Self::try_new(
+            registry,
+            name,
+            help,
+            labels,
+            static_labels,
+            quantiles,
+            max_age,
+            sample,
+            None,
+            SeriesOptions::default(),
+            ConflictPolicy::default(),
+        )
+        .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a new summary metric with the given registry, name, help, labels, static labels,
+    /// quantiles, maximum age of observations and sample rate. If a metric with the same name is
+    /// already registered, the conflict policy decides what happens. See
+    /// [`Counter::try_new`](crate::Counter::try_new) for how static labels, units and series options
+    /// are handled.
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_new<Q: Into<Vec<f64>>>(
+        registry: &prometheus::Registry,
+        name: &str,
+        help: &str,
+        labels: &[&str],
+        static_labels: HashMap<String, String>,
+        quantiles: Option<Q>,
+        max_age: Option<Duration>,
+        sample: Option<f64>,
+        unit: Option<&str>,
+        series: SeriesOptions,
+        on_conflict: ConflictPolicy,
+    ) -> Result<Self, Error> {
+        let quantiles = quantiles.map(Into::into).unwrap_or(DEFAULT_QUANTILES.to_vec());
+        let max_age = max_age.unwrap_or(DEFAULT_MAX_AGE);
+
+        if let Some(q) = quantiles.iter().find(|q| !(**q > 0.0 && **q < 1.0)) {
+            return Err(Error::InvalidOptions(format!(
+                "Invalid quantile {q} for metric {name}: quantiles must be in (0, 1)"
+            )));
+        }
+        if max_age.is_zero() {
+            return Err(Error::InvalidOptions(format!(
+                "Invalid max age for metric {name}: must be non-zero"
+            )));
+        }
+
+        let static_labels = StaticLabels::new(static_labels);
+        let label_names = static_labels.label_names(labels);
+
+        let sampler = sample.map(|rate| Sampler::new(name, &label_names, rate)).transpose()?;
+
+        let desc = Desc::new(
+            name.to_owned(),
+            help.to_owned(),
+            label_names.iter().map(|l| l.to_string()).collect(),
+            HashMap::new(),
+        )?;
+
+        // Use a relative error that gets tighter towards the tails, i.e. 0.05 for the median, 0.01
+        // for the 90th percentile and 0.001 for the 99th percentile.
+        let targets = quantiles.iter().map(|q| (*q, q.min(1.0 - q) / 10.0)).collect();
+
+        let options = MetricOptions {
+            quantiles: Some(quantiles.clone()),
+            max_age: Some(max_age),
+            sample,
+            ..MetricOptions::new(unit)
+        };
+        let metric = SummaryVec { desc, targets, max_age, children: RwLock::default() };
+        let collector = SummaryCollector(Arc::new(metric));
+        let collector = Tracked::new(collector, sampler, Series::new(registry, name, series)?);
+        let Tracked { collector: SummaryCollector(inner), sampler, series, .. } = collector
+            .register(registry, proto::MetricType::SUMMARY, options, &static_labels, on_conflict)?;
+
+        Ok(Self { inner, series, static_labels, sampler })
+    }
+
+    /// Record an observation for the given label values.
+    pub fn observe(&self, labels: &[&str], value: f64) {
+        self.bind(labels).observe(value);
+    }
+
+    /// Remove the series with the given label values.
+    pub fn remove(&self, labels: &[&str]) {
+        self.static_labels.with_values(labels, |values| {
+            self.series.remove(&*self.inner, self.sampler.as_ref(), values);
+        });
+    }
+
+    /// Remove all series of this instance, i.e. with its static label values.
+    pub fn clear(&self) {
+        self.series.clear(&*self.inner, self.sampler.as_ref(), &self.static_labels);
+    }
+
+    /// Resolve the summary for the given label values.
+    pub fn bind(&self, labels: &[&str]) -> SummaryHandle {
+        self.series.with_values(&self.static_labels, labels, |labels| {
+            let sampler = self.sampler.as_ref().map(|sampler| sampler.bind(labels));
+            SummaryHandle::new(SummaryChild(self.inner.child(labels)), sampler)
+        })
+    }
+}
+
+/// The shared state of a summary metric and all of its label combinations.
+#[derive(Debug)]
+struct SummaryVec {
+    desc: Desc,
+    /// The targeted quantiles and their allowed relative error.
+    targets: Arc<[(f64, f64)]>,
+    max_age: Duration,
+    /// The summaries by their label values.
+    children: RwLock<HashMap<SeriesKey, Arc<Mutex<SummaryCore>>>>,
+}
+
+impl SummaryVec {
+    /// Get or create the summary for the given label values.
+    fn child(&self, labels: &[&str]) -> Arc<Mutex<SummaryCore>> {
+        assert_eq!(
+            labels.len(),
+            self.desc.variable_labels.len(),
+            "Inconsistent label cardinality for metric {}",
+            self.desc.fq_name
+        );
+
+        if let Some(child) = self.children.read().unwrap().get(&labels as &dyn Values) {
+            return child.clone();
+        }
+
+        self.children
+            .write()
+            .unwrap()
+            .entry(SeriesKey::new(labels))
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(SummaryCore::new(self.targets.clone(), self.max_age)))
+            })
+            .clone()
+    }
+
+    fn label_pairs(&self, values: &[String]) -> Vec<proto::LabelPair> {
+        let mut pairs = self.desc.const_label_pairs.clone();
+        pairs.extend(self.desc.variable_labels.iter().zip(values).map(|(name, value)| {
+            let mut pair = proto::LabelPair::default();
+            pair.set_name(name.clone());
+            pair.set_value(value.clone());
+            pair
+        }));
+        pairs.sort();
+        pairs
+    }
+}
+
+/// The summary for a single label combination.
+#[derive(Debug, Clone)]
+pub(crate) struct SummaryChild(Arc<Mutex<SummaryCore>>);
+
+impl SummaryChild {
+    pub(crate) fn observe(&self, value: f64) {
+        self.0.lock().unwrap().observe(value, Instant::now());
+    }
+}
+
+/// Wrapper to register a [`SummaryVec`] with a [`prometheus::Registry`].
+#[derive(Debug, Clone)]
+struct SummaryCollector(Arc<SummaryVec>);
+
+impl Collector for SummaryCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.0.desc]
+    }
+
+    fn collect(&self) -> Vec<proto::MetricFamily> {
+        let vec = &self.0;
+        let now = Instant::now();
+
+        let metrics = vec
+            .children
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(labels, child)| {
+                let mut core = child.lock().unwrap();
+
+                let mut summary = proto::Summary::default();
+                summary.set_sample_count(core.count);
+                summary.set_sample_sum(core.sum);
+                summary.set_quantile(
+                    core.quantiles(now)
+                        .into_iter()
+                        .map(|(q, v)| {
+                            let mut quantile = proto::Quantile::default();
+                            quantile.set_quantile(q);
+                            quantile.set_value(v);
+                            quantile
+                        })
+                        .collect(),
+                );
+
+                let mut metric = proto::Metric::default();
+                metric.set_label(vec.label_pairs(labels.values()));
+                metric.set_summary(summary);
+                metric
+            })
+            .collect();
+
+        let mut family = proto::MetricFamily::default();
+        family.set_name(vec.desc.fq_name.clone());
+        family.set_help(vec.desc.help.clone());
+        family.set_field_type(proto::MetricType::SUMMARY);
+        family.set_metric(metrics);
+
+        vec![family]
+    }
+}
+
+impl RemoveSeries for SummaryVec {
+    fn remove_series(&self, values: &[&str]) {
+        self.children.write().unwrap().remove(&values as &dyn Values);
+    }
+
+    fn series_values(&self) -> Vec<Vec<String>> {
+        let children = self.children.read().unwrap();
+        children.keys().map(|labels| labels.values().to_vec()).collect()
+    }
+}
+
+impl RemoveSeries for SummaryCollector {
+    fn remove_series(&self, values: &[&str]) {
+        self.0.remove_series(values);
+    }
+
+    fn series_values(&self) -> Vec<Vec<String>> {
+        self.0.series_values()
+    }
+}
+
+/// The state of a single summary (i.e. one label combination).
+#[derive(Debug)]
+struct SummaryCore {
+    /// The ring of streams making up the sliding window. `head` is the oldest stream, which is the
+    /// one that is queried.
+    streams: Vec<Stream>,
+    head: usize,
+    head_expires: Instant,
+    stream_duration: Duration,
+    count: u64,
+    sum: f64,
+}
+
+impl SummaryCore {
+    fn new(targets: Arc<[(f64, f64)]>, max_age: Duration) -> Self {
+        // No need to keep any streams around if there are no quantiles to report.
+        let streams = if targets.is_empty() {
+            Vec::new()
+        } else {
+            (0..AGE_BUCKETS).map(|_| Stream::new(targets.clone())).collect()
+        };
+        let stream_duration = max_age / AGE_BUCKETS;
+
+        Self {
+            streams,
+            head: 0,
+            head_expires: Instant::now() + stream_duration,
+            stream_duration,
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64, now: Instant) {
+        self.rotate(now);
+        self.streams.iter_mut().for_each(|stream| stream.insert(value));
+        self.count += 1;
+        self.sum += value;
+    }
+
+    fn quantiles(&mut self, now: Instant) -> Vec<(f64, f64)> {
+        self.rotate(now);
+        let Some(stream) = self.streams.get_mut(self.head) else {
+            return Vec::new();
+        };
+
+        let targets = stream.targets.clone();
+        targets.iter().map(|(q, _)| (*q, stream.query(*q))).collect()
+    }
+
+    /// Reset expired streams and advance the head of the ring.
+    fn rotate(&mut self, now: Instant) {
+        if now < self.head_expires || self.streams.is_empty() {
+            return;
+        }
+
+        // Everything has expired, start from scratch.
+        if now.duration_since(self.head_expires) >= self.stream_duration * AGE_BUCKETS {
+            self.streams.iter_mut().for_each(Stream::reset);
+            self.head_expires = now + self.stream_duration;
+            return;
+        }
+
+        while now >= self.head_expires {
+            self.streams[self.head].reset();
+            self.head = (self.head + 1) % self.streams.len();
+            self.head_expires += self.stream_duration;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    value: f64,
+    width: f64,
+    delta: f64,
+}
+
+/// A targeted quantile estimator stream.
+#[derive(Debug)]
+struct Stream {
+    targets: Arc<[(f64, f64)]>,
+    samples: Vec<Sample>,
+    n: f64,
+    buffer: Vec<f64>,
+    sorted: bool,
+}
+
+impl Stream {
+    fn new(targets: Arc<[(f64, f64)]>) -> Self {
+        Self {
+            targets,
+            samples: Vec::new(),
+            n: 0.0,
+            buffer: Vec::with_capacity(BUFFER_CAP),
+            sorted: true,
+        }
+    }
+
+    fn insert(&mut self, value: f64) {
+        self.buffer.push(value);
+        self.sorted = false;
+        if self.buffer.len() == BUFFER_CAP {
+            self.flush();
+        }
+    }
+
+    fn query(&mut self, q: f64) -> f64 {
+        // Fast path when there hasn't been enough data for a flush. This also yields better
+        // accuracy for small sets of data.
+        if self.samples.is_empty() {
+            if self.buffer.is_empty() {
+                return f64::NAN;
+            }
+
+            self.sort_buffer();
+            let i = ((self.buffer.len() as f64 * q).ceil() as usize).saturating_sub(1);
+            return self.buffer[i];
+        }
+
+        self.flush();
+
+        let mut t = (q * self.n).ceil();
+        t += (self.invariant(t) / 2.0).ceil();
+
+        let mut prev = self.samples[0];
+        let mut r = 0.0;
+        for sample in &self.samples[1..] {
+            r += prev.width;
+            if r + sample.width + sample.delta > t {
+                return prev.value;
+            }
+            prev = *sample;
+        }
+
+        prev.value
+    }
+
+    fn reset(&mut self) {
+        self.samples.clear();
+        self.buffer.clear();
+        self.n = 0.0;
+        self.sorted = true;
+    }
+
+    fn sort_buffer(&mut self) {
+        if !self.sorted {
+            self.buffer.sort_by(f64::total_cmp);
+            self.sorted = true;
+        }
+    }
+
+    fn flush(&mut self) {
+        self.sort_buffer();
+        let buffer = std::mem::take(&mut self.buffer);
+        self.merge(&buffer);
+
+        // Reuse the allocation.
+        self.buffer = buffer;
+        self.buffer.clear();
+    }
+
+    /// Merge the sorted values into the samples.
+    fn merge(&mut self, values: &[f64]) {
+        let mut r = 0.0;
+        let mut i = 0;
+
+        for value in values {
+            let mut inserted = false;
+            while i < self.samples.len() {
+                let sample = self.samples[i];
+                if sample.value > *value {
+                    let delta = (self.invariant(r).floor() - 1.0).max(0.0);
+                    self.samples.insert(i, Sample { value: *value, width: 1.0, delta });
+                    i += 1;
+                    inserted = true;
+                    break;
+                }
+                r += sample.width;
+                i += 1;
+            }
+
+            if !inserted {
+                self.samples.push(Sample { value: *value, width: 1.0, delta: 0.0 });
+                i += 1;
+            }
+
+            self.n += 1.0;
+            r += 1.0;
+        }
+
+        self.compress();
+    }
+
+    fn compress(&mut self) {
+        if self.samples.len() < 2 {
+            return;
+        }
+
+        let mut xi = self.samples.len() - 1;
+        let mut x = self.samples[xi];
+        let mut r = self.n - 1.0 - x.width;
+
+        for i in (0..self.samples.len() - 1).rev() {
+            let c = self.samples[i];
+            if c.width + x.width + x.delta <= self.invariant(r) {
+                x.width += c.width;
+                self.samples[xi] = x;
+                self.samples.remove(i);
+                xi -= 1;
+            } else {
+                x = c;
+                xi = i;
+            }
+            r -= c.width;
+        }
+    }
+
+    /// The maximum allowed error at rank `r`.
+    fn invariant(&self, r: f64) -> f64 {
+        self.targets
+            .iter()
+            .map(|(q, eps)| {
+                if q * self.n <= r {
+                    (2.0 * eps * r) / q
+                } else {
+                    (2.0 * eps * (self.n - r)) / (1.0 - q)
+                }
+            })
+            .fold(f64::MAX, f64::min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets() -> Arc<[(f64, f64)]> {
+        DEFAULT_QUANTILES.iter().map(|q| (*q, q.min(1.0 - q) / 10.0)).collect()
+    }
+
+    #[test]
+    fn test_stream_quantiles() {
+        let mut stream = Stream::new(targets());
+
+        // Insert the values out of order to exercise the merge.
+        for i in (0..10_000).rev() {
+            stream.insert(((i * 7919) % 10_000) as f64);
+        }
+
+        for (q, eps) in targets().iter() {
+            let value = stream.query(*q);
+            let expected = q * 10_000.0;
+            assert!(
+                (value - expected).abs() <= 2.0 * eps * 10_000.0,
+                "q{q}: got {value}, expected {expected}"
+            );
+        }
+
+        // The stream should be compressed well below the number of observations.
+        assert!(stream.samples.len() < 1_000);
+    }
+
+    #[test]
+    fn test_children() {
+        let registry = prometheus::Registry::new();
+        let summary = Summary::new(
+            &registry,
+            "test_children",
+            "Test summary",
+            &["a", "b"],
+            HashMap::new(),
+            None::<Vec<f64>>,
+            None,
+            None,
+        );
+
+        // Summaries are told apart by their label values, not by a hash of them.
+        summary.observe(&["ab", ""], 1.0);
+        summary.observe(&["a", "b"], 2.0);
+        summary.observe(&["a", "b"], 3.0);
+
+        let mut values = summary.inner.series_values();
+        values.sort();
+        assert_eq!(values, [["a", "b"], ["ab", ""]]);
+
+        summary.remove(&["ab", ""]);
+        assert_eq!(summary.inner.series_values(), [["a", "b"]]);
+        assert_eq!(summary.inner.child(&["a", "b"]).lock().unwrap().count, 2);
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let start = Instant::now();
+        let mut core = SummaryCore::new(targets(), Duration::from_secs(10));
+
+        (0..100).for_each(|_| core.observe(1.0, start));
+
+        let quantiles = core.quantiles(start);
+        assert!(quantiles.iter().all(|(_, v)| *v == 1.0));
+
+        // After the window has passed, the old observations should be gone.
+        let later = start + Duration::from_secs(11);
+        (0..100).for_each(|_| core.observe(2.0, later));
+
+        let quantiles = core.quantiles(later);
+        assert!(quantiles.iter().all(|(_, v)| *v == 2.0));
+
+        // The count and sum are cumulative.
+        assert_eq!(core.count, 200);
+        assert_eq!(core.sum, 300.0);
+    }
+}
@@ -0,0 +1,406 @@
+//! Helpers for testing metrics, enabled with the `testing` feature.
+//!
+//! Metrics built without a registry are registered with the default registry, which is shared by
+//! all tests of a binary. Tests that run in parallel should build their metrics with their own
+//! [`TestRegistry`] instead (note that `static` metrics always use the default registry).
+//!
+//! # Example
+//! ```rust
+//! use prometric::{Counter, assert_counter, testing::TestRegistry};
+//!
+//! let registry = TestRegistry::new();
+//! let errors = Counter::<u64>::new(&registry, "app_errors", "Errors", &["kind"], Default::default());
+//! errors.inc_by(&["timeout"], 3);
+//!
+//! assert_counter!(registry, "app_errors", { "kind" => "timeout" }, 3);
+//!
+//! // Compare the exposition text, without the values.
+//! prometric::assert_exposition!(
+//!     registry,
+//!     r#"
+//!     ## HELP app_errors Errors
+//!     ## TYPE app_errors counter
+//!     app_errors{kind="timeout"} <value>
+//!     "#
+//! );
+//! ```
+
+use std::{collections::BTreeMap, fmt, ops::Deref};
+
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+
+/// The placeholder for sample values in [`mask_values`].
+pub const MASK: &str = "<value>";
+
+/// A registry for a single test, so that tests don't interfere with each other. Dereferences to
+/// the underlying [`prometheus::Registry`], so it can be passed to `with_registry`.
+#[derive(Debug, Clone, Default)]
+pub struct TestRegistry {
+    registry: prometheus::Registry,
+}
+
+impl TestRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gather the metrics of the registry as samples.
+    pub fn samples(&self) -> Samples {
+        Samples::from_families(&self.registry.gather())
+    }
+
+    /// Encode the metrics of the registry in the Prometheus text format.
+    pub fn text(&self) -> String {
+        prometheus::TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .expect("Failed to encode the metrics")
+    }
+
+    /// Encode the metrics of the registry in the Prometheus text format, with the sample values
+    /// masked. See [`mask_values`].
+    pub fn masked_text(&self) -> String {
+        mask_values(&self.text())
+    }
+}
+
+impl Deref for TestRegistry {
+    type Target = prometheus::Registry;
+
+    fn deref(&self) -> &Self::Target {
+        &self.registry
+    }
+}
+
+/// A single sample of a gathered metric, as it appears in the text format. Histograms and
+/// summaries are split into their `_bucket`, `_sum` and `_count` samples and quantiles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// The name of the sample, e.g. `app_latency_bucket`.
+    pub name: String,
+    /// The labels of the sample, including `le` and `quantile`.
+    pub labels: BTreeMap<String, String>,
+    pub value: f64,
+    /// The type of the metric family the sample belongs to.
+    pub kind: MetricType,
+}
+
+impl Sample {
+    fn new(name: impl Into<String>, pairs: &[LabelPair], value: f64, kind: MetricType) -> Self {
+        let labels =
+            pairs.iter().map(|pair| (pair.name().to_owned(), pair.value().to_owned())).collect();
+        Self { name: name.into(), labels, value, kind }
+    }
+
+    fn with_label(mut self, name: &str, value: String) -> Self {
+        self.labels.insert(name.to_owned(), value);
+        self
+    }
+
+    /// Returns whether the sample has all of the given labels.
+    pub fn has_labels(&self, labels: &[(&str, &str)]) -> bool {
+        labels.iter().all(|(name, value)| self.labels.get(*name).is_some_and(|v| v == value))
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.labels.is_empty() {
+            let labels = self.labels.iter().map(|(name, value)| format!("{name}={value:?}"));
+            write!(f, "{{{}}}", labels.collect::<Vec<_>>().join(","))?;
+        }
+        write!(f, " {}", self.value)
+    }
+}
+
+/// A parsed view of gathered metric families, as a flat list of samples.
+#[derive(Debug, Clone, Default)]
+pub struct Samples {
+    samples: Vec<Sample>,
+}
+
+impl Samples {
+    pub fn from_families(families: &[MetricFamily]) -> Self {
+        let mut samples = Vec::new();
+
+        for family in families {
+            let name = family.name();
+            let kind = family.get_field_type();
+
+            for metric in family.get_metric() {
+                let labels = metric.get_label();
+                match kind {
+                    MetricType::COUNTER => {
+                        samples.push(Sample::new(name, labels, metric.get_counter().value(), kind));
+                    }
+                    MetricType::GAUGE => {
+                        samples.push(Sample::new(name, labels, metric.get_gauge().value(), kind));
+                    }
+                    MetricType::UNTYPED => {
+                        samples.push(Sample::new(name, labels, metric.untyped.value(), kind));
+                    }
+                    MetricType::HISTOGRAM => {
+                        let histogram = metric.get_histogram();
+                        let bucket = format!("{name}_bucket");
+                        for b in histogram.get_bucket() {
+                            let count = b.cumulative_count() as f64;
+                            samples.push(
+                                Sample::new(&bucket, labels, count, kind)
+                                    .with_label("le", format_bound(b.upper_bound())),
+                            );
+                        }
+
+                        let count = histogram.sample_count() as f64;
+                        samples.push(
+                            Sample::new(&bucket, labels, count, kind)
+                                .with_label("le", "+Inf".to_owned()),
+                        );
+                        samples.push(Sample::new(
+                            format!("{name}_sum"),
+                            labels,
+                            histogram.sample_sum(),
+                            kind,
+                        ));
+                        samples.push(Sample::new(format!("{name}_count"), labels, count, kind));
+                    }
+                    MetricType::SUMMARY => {
+                        let summary = metric.get_summary();
+                        for q in summary.get_quantile() {
+                            samples.push(
+                                Sample::new(name, labels, q.value(), kind)
+                                    .with_label("quantile", format_bound(q.quantile())),
+                            );
+                        }
+
+                        samples.push(Sample::new(
+                            format!("{name}_sum"),
+                            labels,
+                            summary.sample_sum(),
+                            kind,
+                        ));
+                        samples.push(Sample::new(
+                            format!("{name}_count"),
+                            labels,
+                            summary.sample_count() as f64,
+                            kind,
+                        ));
+                    }
+                }
+            }
+        }
+
+        Self { samples }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Sample> {
+        self.samples.iter()
+    }
+
+    /// Returns the samples with the given name that have all of the given labels.
+    pub fn find(&self, name: &str, labels: &[(&str, &str)]) -> Vec<&Sample> {
+        self.samples.iter().filter(|s| s.name == name && s.has_labels(labels)).collect()
+    }
+
+    /// Returns the value of the only sample with the given name that has all of the given labels,
+    /// or `None` if there's no such sample or more than one.
+    pub fn value(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        match self.find(name, labels)[..] {
+            [sample] => Some(sample.value),
+            _ => None,
+        }
+    }
+}
+
+/// Format a bucket bound or quantile like the text format does.
+fn format_bound(value: f64) -> String {
+    if value == f64::INFINITY { "+Inf".to_owned() } else { value.to_string() }
+}
+
+/// Replace the sample values (and timestamps) in the text format with [`MASK`], so that the
+/// output can be compared regardless of the recorded values.
+pub fn mask_values(text: &str) -> String {
+    let mut masked = String::with_capacity(text.len());
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('#') {
+            masked.push_str(line);
+        } else {
+            // NOTE: Label values may contain spaces, so the series ends at the closing brace.
+            let end = match line.rfind('}') {
+                Some(end) => end + 1,
+                None => line.find(' ').unwrap_or(line.len()),
+            };
+            masked.push_str(&line[..end]);
+            masked.push(' ');
+            masked.push_str(MASK);
+        }
+        masked.push('\n');
+    }
+
+    masked
+}
+
+/// Assert that the registry has exactly one sample of the given type, name and labels, with the
+/// expected value. Used by [`assert_counter!`](crate::assert_counter) and
+/// [`assert_gauge!`](crate::assert_gauge).
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_sample(
+    registry: &prometheus::Registry,
+    kind: MetricType,
+    name: &str,
+    labels: &[(&str, &str)],
+    expected: f64,
+) {
+    let samples = Samples::from_families(&registry.gather());
+    let kind_name = format!("{kind:?}").to_lowercase();
+    let labels_str =
+        labels.iter().map(|(name, value)| format!("{name}={value:?}")).collect::<Vec<_>>();
+    let series = format!("{name}{{{}}}", labels_str.join(","));
+
+    let matching = samples.find(name, labels);
+    let list = |samples: &[&Sample]| {
+        samples.iter().map(|s| format!("\n  {s}")).collect::<Vec<_>>().join("")
+    };
+
+    match matching[..] {
+        [] => {
+            let existing = samples.iter().filter(|s| s.name == name).collect::<Vec<_>>();
+            panic!("No {kind_name} series matches {series}. Existing series:{}", list(&existing));
+        }
+        [sample] if sample.kind != kind => {
+            let actual = format!("{:?}", sample.kind).to_lowercase();
+            panic!("Expected {series} to be a {kind_name}, but it is a {actual}");
+        }
+        [sample] => assert!(
+            sample.value == expected,
+            "Expected {kind_name} {series} to be {expected}, but it is {}",
+            sample.value
+        ),
+        _ => panic!("Multiple series match {series}:{}", list(&matching)),
+    }
+}
+
+/// Assert that the registry has exactly one counter series with the given name and labels, and
+/// that it has the expected value. The labels only need to be a subset of the labels of the
+/// series.
+///
+/// ```rust
+/// # use prometric::{Counter, assert_counter, testing::TestRegistry};
+/// # let registry = TestRegistry::new();
+/// # let errors = Counter::<u64>::new(&registry, "app_errors", "Errors", &["host"], Default::default());
+/// # errors.inc_by(&["x"], 3);
+/// assert_counter!(registry, "app_errors", { "host" => "x" }, 3);
+/// ```
+#[macro_export]
+macro_rules! assert_counter {
+    ($registry:expr, $name:expr, { $($key:expr => $value:expr),* $(,)? }, $expected:expr $(,)?) => {
+        $crate::testing::assert_sample(
+            &$registry,
+            prometheus::proto::MetricType::COUNTER,
+            $name,
+            &[$(($key, $value)),*],
+            $expected as f64,
+        )
+    };
+    ($registry:expr, $name:expr, $expected:expr $(,)?) => {
+        $crate::assert_counter!($registry, $name, {}, $expected)
+    };
+}
+
+/// Assert that the registry has exactly one gauge series with the given name and labels, and that
+/// it has the expected value. See [`assert_counter!`](crate::assert_counter).
+#[macro_export]
+macro_rules! assert_gauge {
+    ($registry:expr, $name:expr, { $($key:expr => $value:expr),* $(,)? }, $expected:expr $(,)?) => {
+        $crate::testing::assert_sample(
+            &$registry,
+            prometheus::proto::MetricType::GAUGE,
+            $name,
+            &[$(($key, $value)),*],
+            $expected as f64,
+        )
+    };
+    ($registry:expr, $name:expr, $expected:expr $(,)?) => {
+        $crate::assert_gauge!($registry, $name, {}, $expected)
+    };
+}
+
+/// Assert that the text format of the registry matches the expected text, with the sample values
+/// masked. Leading whitespace and empty lines are ignored, so the expected text can be indented.
+#[macro_export]
+macro_rules! assert_exposition {
+    ($registry:expr, $expected:expr $(,)?) => {{
+        let families = prometheus::Registry::gather(&$registry);
+        let text = prometheus::TextEncoder::new()
+            .encode_to_string(&families)
+            .expect("Failed to encode the metrics");
+        assert_eq!($crate::testing::mask_values(&text), $crate::testing::mask_values($expected),);
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Counter, Gauge, Histogram};
+
+    #[test]
+    fn test_samples() {
+        let registry = TestRegistry::new();
+        let requests =
+            Counter::<u64>::new(&registry, "requests", "Requests", &["method"], Default::default());
+        let latency =
+            Histogram::new(&registry, "latency", "Latency", &[], Default::default(), Some([1.0]));
+        requests.inc_by(&["GET"], 2);
+        requests.inc(&["POST"]);
+        latency.observe(&[], 0.5);
+
+        let samples = registry.samples();
+        assert_eq!(samples.value("requests", &[("method", "GET")]), Some(2.0));
+        // Ambiguous or missing samples have no value.
+        assert_eq!(samples.value("requests", &[]), None);
+        assert_eq!(samples.value("requests", &[("method", "PUT")]), None);
+        assert_eq!(samples.value("latency_bucket", &[("le", "1")]), Some(1.0));
+        assert_eq!(samples.value("latency_bucket", &[("le", "+Inf")]), Some(1.0));
+        assert_eq!(samples.value("latency_sum", &[]), Some(0.5));
+
+        assert_counter!(registry, "requests", { "method" => "POST" }, 1);
+        assert_exposition!(
+            registry,
+            r#"
+            # HELP latency Latency
+            # TYPE latency histogram
+            latency_bucket{le="1"} <value>
+            latency_bucket{le="+Inf"} <value>
+            latency_sum <value>
+            latency_count <value>
+            # HELP requests Requests
+            # TYPE requests counter
+            requests{method="GET"} <value>
+            requests{method="POST"} <value>
+            "#
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "to be a counter, but it is a gauge")]
+    fn test_assert_kind() {
+        let registry = TestRegistry::new();
+        let gauge = Gauge::<i64>::new(&registry, "queued", "Queued", &[], Default::default());
+        gauge.set(&[], 1);
+
+        assert_gauge!(registry, "queued", 1);
+        assert_counter!(registry, "queued", 1);
+    }
+
+    #[test]
+    fn test_mask_values() {
+        let text = "# TYPE a counter\na{path=\"/a b\"} 1 1700000000000\nb 2.5\n";
+        assert_eq!(mask_values(text), "# TYPE a counter\na{path=\"/a b\"} <value>\nb <value>\n");
+    }
+}
@@ -0,0 +1,56 @@
+use std::time::{Duration, Instant};
+
+use crate::Observe;
+
+/// A timer guard that records the elapsed time in seconds when it is dropped, unless it is
+/// stopped explicitly with [`Timer::stop_and_record`] or [`Timer::stop_and_discard`].
+///
+/// Timers are created with the generated `start_timer` method on histogram and summary accessors.
+#[derive(Debug)]
+#[must_use = "Timers record the elapsed time when dropped, so they should be bound to a variable"]
+pub struct Timer<H: Observe> {
+    handle: H,
+    start: Instant,
+    observed: bool,
+}
+
+impl<H: Observe> Timer<H> {
+    /// Start a new timer that records into the given metric handle.
+    pub fn new(handle: H) -> Self {
+        Self { handle, start: Instant::now(), observed: false }
+    }
+
+    /// The time elapsed since the timer was started.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Stop the timer and record the elapsed time. Returns the elapsed time in seconds.
+    pub fn stop_and_record(mut self) -> f64 {
+        self.stop(true)
+    }
+
+    /// Stop the timer without recording the elapsed time. Returns the elapsed time in seconds.
+    pub fn stop_and_discard(mut self) -> f64 {
+        self.stop(false)
+    }
+
+    fn stop(&mut self, record: bool) -> f64 {
+        let elapsed = self.elapsed().as_secs_f64();
+        self.observed = true;
+
+        if record {
+            self.handle.observe(elapsed);
+        }
+
+        elapsed
+    }
+}
+
+impl<H: Observe> Drop for Timer<H> {
+    fn drop(&mut self) {
+        if !self.observed {
+            self.stop(true);
+        }
+    }
+}